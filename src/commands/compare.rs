@@ -0,0 +1,444 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use tracing::warn;
+
+use super::CompareOpts;
+use crate::stats::ops::Throughput;
+use crate::stats::report::{OperationSummary, RunReport};
+
+/// A metric of an operation that `compare` can check
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    Throughput,
+    ObjectsPerSec,
+    Min,
+    Avg,
+    Max,
+    /// A latency percentile by its label, e.g. `p99.9`
+    Percentile(String),
+}
+
+impl Metric {
+    fn value(&self, summary: &OperationSummary) -> Option<f64> {
+        let latency = summary.latency_ms.as_ref();
+        match self {
+            Metric::Throughput => Some(summary.throughput_bytes_per_sec),
+            Metric::ObjectsPerSec => Some(summary.objects_per_sec),
+            Metric::Min => latency.map(|latency| latency.min),
+            Metric::Avg => latency.map(|latency| latency.avg),
+            Metric::Max => latency.map(|latency| latency.max),
+            Metric::Percentile(label) => {
+                latency.and_then(|latency| latency.percentiles.get(label).copied())
+            }
+        }
+    }
+
+    fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::Throughput | Metric::ObjectsPerSec)
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self {
+            Metric::Throughput => Throughput(value).to_string(),
+            Metric::ObjectsPerSec => format!("{value:.1}"),
+            _ => format!("{value:.1}ms"),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "throughput" => Ok(Metric::Throughput),
+            "objects-per-sec" => Ok(Metric::ObjectsPerSec),
+            "min" => Ok(Metric::Min),
+            "avg" => Ok(Metric::Avg),
+            "max" => Ok(Metric::Max),
+            label if is_percentile(label) => Ok(Metric::Percentile(label.to_string())),
+            _ => bail!(
+                "unknown metric {s}, expected throughput, objects-per-sec, min, avg, max \
+                 or a percentile like p99"
+            ),
+        }
+    }
+}
+
+fn is_percentile(label: &str) -> bool {
+    label
+        .strip_prefix('p')
+        .and_then(|percent| percent.parse::<f64>().ok())
+        .is_some_and(|percent| (0.0..100.0).contains(&percent))
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Throughput => write!(f, "Throughput"),
+            Metric::ObjectsPerSec => write!(f, "Objects/s"),
+            Metric::Min => write!(f, "Min"),
+            Metric::Avg => write!(f, "Avg"),
+            Metric::Max => write!(f, "Max"),
+            Metric::Percentile(label) => write!(f, "{label}"),
+        }
+    }
+}
+
+/// Fails the comparison when `metric` gets worse by more than `max_regression` percent,
+/// written as `<metric>=<percent>` (e.g. `p99=10`)
+#[derive(Debug, Clone)]
+pub struct Threshold {
+    pub metric: Metric,
+    pub max_regression: f64,
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (metric, percent) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <metric>=<percent>, got {s}"))?;
+        Ok(Threshold {
+            metric: metric.parse()?,
+            max_regression: percent
+                .trim_end_matches('%')
+                .parse()
+                .with_context(|| format!("invalid percentage in {s}"))?,
+        })
+    }
+}
+
+/// Returned by `compare` when the candidate crossed at least one threshold
+#[derive(Debug)]
+pub struct RegressionError {
+    pub regressions: usize,
+}
+
+impl fmt::Display for RegressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} metrics regressed beyond their threshold",
+            self.regressions
+        )
+    }
+}
+
+impl std::error::Error for RegressionError {}
+
+pub async fn compare(opts: CompareOpts) -> Result<()> {
+    let baseline = read_report(&opts.baseline)?;
+    let candidate = read_report(&opts.candidate)?;
+    if baseline.metadata.config_hash != candidate.metadata.config_hash {
+        warn!("the results come from different test configs");
+    }
+
+    let regressions = compare_reports(&baseline, &candidate, &opts)?;
+    if regressions > 0 {
+        return Err(RegressionError { regressions }.into());
+    }
+    Ok(())
+}
+
+/// Prints the change of every metric and returns how many crossed their threshold. An
+/// operation or a checked metric that is missing from the candidate counts as a regression
+fn compare_reports(
+    baseline: &RunReport,
+    candidate: &RunReport,
+    opts: &CompareOpts,
+) -> Result<usize> {
+    let metrics = [
+        Metric::Throughput,
+        Metric::ObjectsPerSec,
+        Metric::Min,
+        Metric::Avg,
+        Metric::Max,
+    ]
+    .into_iter()
+    .chain(
+        baseline
+            .operations
+            .iter()
+            .filter_map(|op| op.latency_ms.as_ref())
+            .flat_map(|latency| latency.percentiles.keys())
+            .map(|label| Metric::Percentile(label.clone())),
+    )
+    .fold(Vec::new(), |mut metrics, metric| {
+        if !metrics.contains(&metric) {
+            metrics.push(metric);
+        }
+        metrics
+    });
+    for threshold in &opts.thresholds {
+        let checked = metrics.contains(&threshold.metric)
+            && baseline
+                .operations
+                .iter()
+                .any(|op| threshold.metric.value(op).is_some());
+        if !checked {
+            bail!(
+                "{} has no {} to check the threshold against",
+                opts.baseline.display(),
+                threshold.metric
+            );
+        }
+    }
+
+    let mut regressions = 0;
+    for base in &baseline.operations {
+        println!("----------------------------------------------------");
+        match &base.stage {
+            Some(stage) => println!("Operation: {}. Stage: {stage}", base.operation),
+            None => println!("Operation: {}", base.operation),
+        }
+        let Some(cand) = candidate.operations.iter().find(|op| same_stats(op, base)) else {
+            println!("* missing from {} REGRESSION", opts.candidate.display());
+            regressions += 1;
+            continue;
+        };
+        println!("Errors: {} -> {}", base.errors, cand.errors);
+        if base.integrity_errors > 0 || cand.integrity_errors > 0 {
+            println!(
+                "Integrity errors: {} -> {}",
+                base.integrity_errors, cand.integrity_errors
+            );
+        }
+        for metric in &metrics {
+            let Some(before) = metric.value(base) else {
+                continue;
+            };
+            let threshold = opts
+                .thresholds
+                .iter()
+                .find(|threshold| threshold.metric == *metric);
+            let Some(after) = metric.value(cand) else {
+                let mut line = format!("* {metric}: {} -> missing", metric.format(before));
+                if let Some(threshold) = threshold {
+                    regressions += 1;
+                    line.push_str(&format!(
+                        " REGRESSION (threshold {}%)",
+                        threshold.max_regression
+                    ));
+                }
+                println!("{line}");
+                continue;
+            };
+            let delta = change(before, after);
+            let mut line = format!(
+                "* {metric}: {} -> {} ({})",
+                metric.format(before),
+                metric.format(after),
+                delta.map_or("n/a".to_string(), |delta| format!("{delta:+.1}%")),
+            );
+            if let (Some(threshold), Some(delta)) = (threshold, delta) {
+                if regressed(metric, delta, threshold.max_regression) {
+                    regressions += 1;
+                    line.push_str(&format!(
+                        " REGRESSION (threshold {}%)",
+                        threshold.max_regression
+                    ));
+                }
+            }
+            println!("{line}");
+        }
+        println!();
+    }
+    for cand in &candidate.operations {
+        if !baseline.operations.iter().any(|op| same_stats(op, cand)) {
+            let stage = cand
+                .stage
+                .as_ref()
+                .map_or(String::new(), |stage| format!(" of stage {stage}"));
+            println!(
+                "Operation {}{stage} only exists in {}",
+                cand.operation,
+                opts.candidate.display()
+            );
+        }
+    }
+
+    Ok(regressions)
+}
+
+fn read_report(path: &Path) -> Result<RunReport> {
+    let file = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_slice(&file).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Whether two summaries are of the same operation and load profile stage
+fn same_stats(a: &OperationSummary, b: &OperationSummary) -> bool {
+    a.operation == b.operation && a.stage == b.stage
+}
+
+/// Change from `before` to `after` in percent, if there is a baseline to compare with
+fn change(before: f64, after: f64) -> Option<f64> {
+    (before != 0.0).then(|| (after - before) / before * 100.0)
+}
+
+fn regressed(metric: &Metric, delta: f64, max_regression: f64) -> bool {
+    let regression = if metric.higher_is_better() {
+        -delta
+    } else {
+        delta
+    };
+    regression > max_regression
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use crate::stats::report::{LatencySummary, RunMetadata};
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn summary(operation: &str, p99: Option<f64>) -> OperationSummary {
+        OperationSummary {
+            operation: operation.to_string(),
+            stage: None,
+            concurrency: 1,
+            duration_ms: 1000.0,
+            total: 10,
+            errors: 0,
+            integrity_errors: 0,
+            throughput_bytes_per_sec: 1000.0,
+            objects_per_sec: 10.0,
+            latency_ms: p99.map(|p99| LatencySummary {
+                min: 10.0,
+                avg: 50.0,
+                max: p99,
+                percentiles: BTreeMap::from([("p99".to_string(), p99)]),
+            }),
+        }
+    }
+
+    fn report(operations: Vec<OperationSummary>) -> RunReport {
+        RunReport {
+            metadata: RunMetadata {
+                network: "devnet".to_string(),
+                target: Target::Memory,
+                config_hash: String::new(),
+                loader_version: String::new(),
+                started_at: Utc::now(),
+                ended_at: Utc::now(),
+                interrupted: false,
+                circuit_breaker: None,
+            },
+            operations,
+            failed_assertions: Vec::new(),
+            schedule: Vec::new(),
+        }
+    }
+
+    fn opts(thresholds: &[&str]) -> CompareOpts {
+        CompareOpts {
+            baseline: PathBuf::from("baseline.json"),
+            candidate: PathBuf::from("candidate.json"),
+            thresholds: thresholds
+                .iter()
+                .map(|threshold| threshold.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_threshold() {
+        let threshold: Threshold = "p99.9=12.5%".parse().unwrap();
+        assert_eq!(Metric::Percentile("p99.9".to_string()), threshold.metric);
+        assert_eq!(12.5, threshold.max_regression);
+        let threshold: Threshold = "throughput=5".parse().unwrap();
+        assert_eq!(Metric::Throughput, threshold.metric);
+        assert!("latency=5".parse::<Threshold>().is_err());
+        assert!("p99".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn test_regressed() {
+        let p99 = Metric::Percentile("p99".to_string());
+        // latency going up is a regression, going down is not
+        assert!(regressed(&p99, change(100.0, 111.0).unwrap(), 10.0));
+        assert!(!regressed(&p99, change(100.0, 109.0).unwrap(), 10.0));
+        assert!(!regressed(&p99, change(100.0, 50.0).unwrap(), 10.0));
+        // throughput going down is a regression, going up is not
+        assert!(regressed(
+            &Metric::Throughput,
+            change(100.0, 89.0).unwrap(),
+            10.0
+        ));
+        assert!(!regressed(
+            &Metric::Throughput,
+            change(100.0, 200.0).unwrap(),
+            10.0
+        ));
+        assert_eq!(None, change(0.0, 1.0));
+    }
+
+    #[test]
+    fn test_parse_percentile() {
+        assert!(is_percentile("p0"));
+        assert!(is_percentile("p99.9"));
+        for label in ["p", "pNaN", "pinf", "p-1", "p100", "p1e9", "99"] {
+            assert!(!is_percentile(label), "{label}");
+        }
+    }
+
+    #[test]
+    fn test_compare_reports() {
+        let baseline = report(vec![
+            summary("Put", Some(100.0)),
+            summary("Get", Some(100.0)),
+        ]);
+        let same = report(vec![
+            summary("Put", Some(105.0)),
+            summary("Get", Some(100.0)),
+        ]);
+        assert_eq!(
+            0,
+            compare_reports(&baseline, &same, &opts(&["p99=10"])).unwrap()
+        );
+        let slower = report(vec![
+            summary("Put", Some(120.0)),
+            summary("Get", Some(100.0)),
+        ]);
+        assert_eq!(
+            1,
+            compare_reports(&baseline, &slower, &opts(&["p99=10"])).unwrap()
+        );
+        // not checked without a threshold
+        assert_eq!(
+            0,
+            compare_reports(&baseline, &slower, &opts(&["max=50"])).unwrap()
+        );
+
+        // every Put failed and no Get ran at all
+        let broken = report(vec![summary("Put", None)]);
+        assert_eq!(
+            2,
+            compare_reports(&baseline, &broken, &opts(&["p99=10"])).unwrap()
+        );
+        assert_eq!(
+            1,
+            compare_reports(&baseline, &broken, &opts(&["throughput=10"])).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_threshold_needs_a_baseline_metric() {
+        let baseline = report(vec![summary("Put", Some(100.0))]);
+        let err = compare_reports(&baseline, &baseline, &opts(&["p75=5"])).unwrap_err();
+        assert_eq!(
+            "baseline.json has no p75 to check the threshold against",
+            err.to_string()
+        );
+
+        let failed = report(vec![summary("Put", None)]);
+        assert!(compare_reports(&failed, &failed, &opts(&["avg=5"])).is_err());
+        assert!(compare_reports(&failed, &failed, &opts(&["throughput=5"])).is_ok());
+    }
+}
@@ -1,32 +1,32 @@
-use std::sync::Arc;
-
 use anyhow::{bail, Context as _};
 use recall_sdk::{machine::Machine, network::Network};
 use tracing::{error, info};
 
-use super::{list_bucket_items, setup_provider_wallet_bucket, CleanupOpts};
+use super::{
+    account_target, list_bucket_items, setup_provider_wallet_bucket, CleanupOpts, PartialFailure,
+    SetupError,
+};
 
-use crate::config::Target as ConfigTarget;
 use crate::parse_private_key;
-use crate::targets::sdk::SdkTarget;
-use crate::targets::Target;
 
 pub async fn cleanup(opts: CleanupOpts) -> anyhow::Result<()> {
     let key = parse_private_key(&opts.key)?;
     let prefix = opts.prefix.clone();
     let network = opts.network.unwrap_or(Network::Devnet);
     let bucket = opts.bucket;
-    let (provider, signer, machine) = setup_provider_wallet_bucket(key, network, bucket)
+    let (provider, signer, machine) = setup_provider_wallet_bucket(&key, network, bucket)
         .await
-        .context("failed to setup")?;
+        .context(SetupError)?;
 
-    let target = match opts.target {
-        ConfigTarget::Sdk => Arc::new(SdkTarget {
-            provider: provider.clone(),
-            wallet: signer.clone(),
-        }),
-        ConfigTarget::S3 => unimplemented!(),
-    };
+    let target = account_target(
+        opts.target,
+        &provider,
+        &signer,
+        &key.sk,
+        network,
+        &opts.s3,
+        None,
+    )?;
 
     let (data, durations) = list_bucket_items(target.clone(), &machine, &prefix)
         .await
@@ -45,6 +45,8 @@ pub async fn cleanup(opts: CleanupOpts) -> anyhow::Result<()> {
         bail!("found no data to delete in bucket {address} with {prefix}");
     }
 
+    let total = data.len();
+    let mut failed = 0;
     for key in data {
         match target.clone().delete_object(&machine, &key).await {
             Ok(time) => {
@@ -52,9 +54,18 @@ pub async fn cleanup(opts: CleanupOpts) -> anyhow::Result<()> {
             }
             Err(e) => {
                 error!("failed to delete blob with {key}: {e}");
+                failed += 1;
             }
         }
     }
 
+    if failed > 0 {
+        return Err(PartialFailure {
+            what: "deletes",
+            failed,
+            total,
+        }
+        .into());
+    }
     Ok(())
 }
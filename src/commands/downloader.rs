@@ -1,5 +1,7 @@
+use crate::payload::{Digest, HashingWriter, StreamHash};
 use crate::stats::collector::Collector;
 use crate::stats::ops::{Operation, OperationType};
+use crate::stats::warmup::Warmup;
 use crate::targets::Target;
 use chrono::Utc;
 use recall_provider::fvm_shared::address::Address;
@@ -10,8 +12,15 @@ use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
+/// A blob to download, with the digest of its upload if it is known
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub key: String,
+    pub digest: Option<Digest>,
+}
+
 pub struct Downloader {
-    sender: Option<mpsc::Sender<String>>,
+    sender: Option<mpsc::Sender<Blob>>,
     background_tasks: Vec<Option<JoinHandle<()>>>,
 }
 
@@ -23,8 +32,9 @@ impl Downloader {
         bucket_address: Address,
         concurrency: i32,
         blob_size: i64,
+        warmup: Arc<Warmup>,
     ) -> Self {
-        let (sender, receiver) = mpsc::channel::<String>(100);
+        let (sender, receiver) = mpsc::channel::<Blob>(100);
         let rx = Arc::new(Mutex::new(receiver));
         let mut background_tasks = Vec::new();
         // let bucket = Arc::new(bucket.clone());
@@ -33,19 +43,21 @@ impl Downloader {
             let target_clone = target.clone();
             let collector_clone = collector.clone();
             let rx_clone = rx.clone();
+            let warmup = warmup.clone();
             //let bucket_clone = bucket.clone();
             let background_task = tokio::spawn(async move {
                 loop {
                     let mut rx_guard = rx_clone.lock().await;
-                    if let Some(key) = rx_guard.recv().await {
+                    if let Some(blob) = rx_guard.recv().await {
                         drop(rx_guard);
                         if let Err(err) = download_blob(
                             target_clone.clone(),
                             collector_clone.clone(),
                             thread_id.clone(),
                             bucket_address,
-                            &key,
+                            &blob,
                             blob_size,
+                            &warmup,
                         )
                         .await
                         {
@@ -65,14 +77,14 @@ impl Downloader {
         }
     }
 
-    pub async fn download(&self, keys: &Vec<String>) -> Result<(), mpsc::error::SendError<String>> {
-        for key in keys {
+    pub async fn download(&self, blobs: Vec<Blob>) -> Result<(), mpsc::error::SendError<Blob>> {
+        for blob in blobs {
             if let Some(sender) = &self.sender {
-                let _ = sender.send(key.clone()).await?;
+                sender.send(blob).await?;
                 continue;
             }
 
-            return Err(mpsc::error::SendError(key.clone()));
+            return Err(mpsc::error::SendError(blob));
         }
 
         Ok(())
@@ -82,23 +94,36 @@ impl Downloader {
         // Drop the sender to signal the channel is closed
         self.sender.take();
 
-        for task in &mut self.background_tasks {
-            // Await the background task to finish
-            if let Some(task) = task.take() {
+        for slot in &mut self.background_tasks {
+            // Await the background task to finish, it stays in its slot until then so
+            // `abort` can still stop it
+            if let Some(task) = slot {
                 task.await.unwrap(); // Wait for the task to complete
+                *slot = None;
             }
         }
     }
+
+    /// Stops downloading right away, the downloads in flight are dropped
+    pub async fn abort(&mut self) {
+        self.sender.take();
+        for task in self.background_tasks.iter_mut().filter_map(Option::take) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
 }
 
-async fn download_blob(
+pub(crate) async fn download_blob(
     target: Arc<dyn Target>,
     collector: Arc<Collector>,
     thread_id: String,
     bucket_address: Address,
-    key: &str,
+    blob: &Blob,
     size: i64,
+    warmup: &Warmup,
 ) -> anyhow::Result<()> {
+    let key = &blob.key;
     let opts = GetOptions {
         range: None,
         height: Default::default(),
@@ -112,14 +137,21 @@ async fn download_blob(
         op_type: OperationType::Get,
         size,
         file: key.to_string(),
+        warmup: warmup.begin(),
         ..Default::default()
     };
 
-    let obj_file = async_tempfile::TempFile::new().await.unwrap();
+    let hash = StreamHash::default();
+    let writer = HashingWriter::new(tokio::io::sink(), hash.clone());
     let bucket = Bucket::attach(bucket_address).await.unwrap();
-    let result = target
-        .get_object(&bucket, key, Box::new(obj_file), opts.range)
-        .await;
+    let get = target.get_object(&bucket, key, Box::new(writer), opts.range);
+    let mut result = Warmup::scope(operation.warmup, get).await;
+    if let (Ok(_), Some(digest)) = (&result, &blob.digest) {
+        if let Err(err) = hash.verify(key, digest) {
+            operation.corrupted = true;
+            result = Err(err.into());
+        }
+    }
 
     match result {
         Ok(_) => {
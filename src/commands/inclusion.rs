@@ -0,0 +1,214 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::config::InclusionConfig;
+use crate::stats::collector::Collector;
+use crate::stats::ops::{Operation, OperationType};
+use crate::targets::{Inclusion, Target};
+
+/// Polls the transaction of every upload that was broadcast without waiting for a block and
+/// records the time from its broadcast until it was included as a `Commit` operation
+pub struct InclusionTracker {
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    thread_id: String,
+    config: InclusionConfig,
+    permits: Arc<Semaphore>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+/// The transaction of an upload that was broadcast and has to be included
+pub struct Broadcasted {
+    pub hash: String,
+    pub key: String,
+    /// When the upload started
+    pub at: DateTime<Utc>,
+    /// When the upload started on tokio time, which its inclusion time is measured on
+    pub started: Instant,
+    pub warmup: bool,
+}
+
+impl InclusionTracker {
+    pub fn new(
+        target: Arc<dyn Target>,
+        collector: Arc<Collector>,
+        thread_id: String,
+        config: InclusionConfig,
+    ) -> Self {
+        Self {
+            target,
+            collector,
+            thread_id,
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            config,
+            tasks: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    /// Starts polling a broadcast transaction in the background
+    pub fn watch(&self, broadcasted: Broadcasted) {
+        let target = self.target.clone();
+        let collector = self.collector.clone();
+        let thread_id = self.thread_id.clone();
+        let config = self.config.clone();
+        let permits = self.permits.clone();
+        self.tasks.lock().unwrap().spawn(async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            let hash = broadcasted.hash.clone();
+            if let Err(error) = track(target, collector, thread_id, broadcasted, &config).await {
+                error!(?error, %hash, "failed to track transaction");
+            }
+        });
+    }
+
+    /// Waits until every watched transaction was included, failed or timed out. Stops
+    /// polling the rest once `stop` is cancelled
+    pub async fn wait(&self, stop: &CancellationToken) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        tokio::select! {
+            _ = async { while tasks.join_next().await.is_some() {} } => {}
+            _ = stop.cancelled() => {}
+        }
+        tasks.shutdown().await;
+    }
+}
+
+async fn track(
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    thread_id: String,
+    broadcasted: Broadcasted,
+    config: &InclusionConfig,
+) -> Result<()> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut operation = Operation {
+        id: thread_id,
+        start: broadcasted.at,
+        op_type: OperationType::Commit,
+        file: broadcasted.key.clone(),
+        warmup: broadcasted.warmup,
+        ..Default::default()
+    };
+
+    loop {
+        let result = target.inclusion(&broadcasted.hash).await;
+        let elapsed = broadcasted.started.elapsed();
+        let now = broadcasted.at + chrono::Duration::from_std(elapsed)?;
+        match result {
+            Ok(Inclusion::Included) => {
+                operation.end = now;
+                debug!(hash = broadcasted.hash, time = ?operation.duration(), "included");
+                break;
+            }
+            Ok(Inclusion::Failed(err)) => {
+                operation.end = now;
+                operation.error = format!("transaction {} failed: {err}", broadcasted.hash);
+                break;
+            }
+            result if elapsed >= timeout => {
+                operation.end = now;
+                operation.error = match result {
+                    Err(err) => format!("not included within {}ms: {err}", config.timeout_ms),
+                    _ => format!("not included within {}ms", config.timeout_ms),
+                };
+                break;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(config.poll_interval_ms)).await,
+        }
+    }
+    collector.collect(operation).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Broadcast, MemoryConfig};
+    use crate::targets::memory::InMemoryTarget;
+    use crate::targets::Payload;
+    use std::collections::HashMap;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tracks_every_transaction() {
+        let memory = MemoryConfig {
+            inclusion_delay_ms: 200,
+            ..Default::default()
+        };
+        let target = Arc::new(InMemoryTarget::new(memory));
+        let bucket = target.create_bucket().await.unwrap();
+        let collector = Arc::new(Collector::new());
+        let config = InclusionConfig {
+            poll_interval_ms: 20,
+            timeout_ms: 500,
+            concurrency: 4,
+        };
+        let tracker = InclusionTracker::new(
+            target.clone(),
+            collector.clone(),
+            "1".to_string(),
+            config.clone(),
+        );
+        // gives up before the transaction could be included
+        let impatient = InclusionTracker::new(
+            target.clone(),
+            collector.clone(),
+            "2".to_string(),
+            InclusionConfig {
+                timeout_ms: 100,
+                ..config
+            },
+        );
+
+        for key in ["foo/1", "foo/2", "foo/3"] {
+            let at = Utc::now();
+            let started = Instant::now();
+            let hash = target
+                .add_object(
+                    &bucket,
+                    key,
+                    Payload::new(std::io::Cursor::new(vec![7; 64]), 64),
+                    HashMap::new(),
+                    true,
+                    Broadcast::Async,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            let tracker = if key == "foo/3" { &impatient } else { &tracker };
+            tracker.watch(Broadcasted {
+                hash,
+                key: key.to_string(),
+                at,
+                started,
+                warmup: false,
+            });
+        }
+        tracker.wait(&CancellationToken::new()).await;
+        impatient.wait(&CancellationToken::new()).await;
+        drop(tracker);
+        drop(impatient);
+
+        let mut collector = Arc::try_unwrap(collector).ok().unwrap();
+        collector.close().await;
+        let mut ops = collector.get_operations();
+        ops.sort_by(|a, b| a.file.cmp(&b.file));
+        assert_eq!(3, ops.len());
+        for op in &ops[..2] {
+            assert_eq!(OperationType::Commit, op.op_type);
+            assert!(op.error.is_empty(), "{}", op.error);
+            assert_eq!(200, op.duration().num_milliseconds(), "{}", op.file);
+        }
+        assert_eq!("not included within 100ms", ops[2].error);
+        assert_eq!(100, ops[2].duration().num_milliseconds());
+    }
+}
@@ -1,36 +1,120 @@
+mod compare;
 mod delete;
 mod downloader;
+mod inclusion;
 mod query;
+mod replay;
+mod resolver;
 mod runner;
+mod scheduler;
 
+pub use compare::{compare, Metric, RegressionError, Threshold};
 pub use delete::cleanup;
 pub use query::query;
+pub use replay::replay;
 
 use std::default::Default;
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
 use std::{
     path::PathBuf,
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context as _, Result};
 use clap::Args;
 use recall_provider::{fvm_shared::address::Address, json_rpc::JsonRpcProvider};
 use recall_sdk::machine::{bucket::Bucket, Machine};
 use recall_sdk::network::Network;
+use recall_signer::key::SecretKey;
 use recall_signer::{AccountKind, Signer as _, Wallet};
 use runner::TestRunner;
-use tokio::task::JoinSet;
-use tracing::{debug, error, info};
+use scheduler::Schedule;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-use crate::config::{Broadcast, RandomizedNetwork, Target, TestConfig, TestRunConfig, UploadTest};
+use crate::config::{
+    Broadcast, RandomizedNetwork, S3Config, Target, TestConfig, TestRunConfig, UploadTest,
+};
+use crate::stats::assertions::{self, AssertionError};
+use crate::stats::breaker::Breaker;
 use crate::stats::collector::Collector;
+use crate::stats::export::{write_operations, OpsFormat};
+use crate::stats::report::{RunMetadata, RunReport};
+use crate::targets::http::{HttpClient, HttpTarget};
+use crate::targets::recorder::TraceRecorder;
+use crate::targets::s3::S3Target;
+use crate::targets::sdk::SdkTarget;
 use crate::KeyData;
 
 #[derive(Args, Debug, Clone)]
 pub struct RunTestOpts {
     #[arg(short, long)]
     pub path: PathBuf,
+    #[command(flatten)]
+    pub outputs: OutputOpts,
+}
+
+/// Files a test run writes besides the printed results
+#[derive(Args, Debug, Clone, Default)]
+pub struct OutputOpts {
+    /// Record every call made to the target as a JSON lines trace that `replay` can play back
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Write every operation (thread id, type, key, size, timings and error) to this file
+    #[arg(long)]
+    pub ops_out: Option<PathBuf>,
+    /// Format of the --ops-out file
+    #[arg(long, default_value = "csv")]
+    pub ops_format: OpsFormat,
+    /// Write the aggregated results and the run metadata to this file as JSON
+    #[arg(long)]
+    pub results_out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+/// Will compare two --results-out files and fail on regressions
+pub struct CompareOpts {
+    /// Results of the reference run
+    #[arg(short, long)]
+    pub baseline: PathBuf,
+    /// Results of the run to check
+    #[arg(short, long)]
+    pub candidate: PathBuf,
+    /// Maximum regression in percent per metric, as <metric>=<percent>. Metrics are
+    /// throughput, objects-per-sec, min, avg, max and percentiles like p99. Can be repeated
+    #[arg(long = "threshold", default_value = "p99=10")]
+    pub thresholds: Vec<Threshold>,
+}
+
+#[derive(Args, Debug, Clone)]
+/// Will play back a trace recorded with --record against any target
+pub struct ReplayOpts {
+    /// The trace file to replay
+    #[arg(short, long)]
+    pub trace: PathBuf,
+    /// Playback speed relative to the recording (2.0 = twice as fast). 0 sends everything at once
+    #[arg(long, default_value = "1.0")]
+    pub speed: f64,
+    /// The private key to use for the signer wallet (not needed for the memory target)
+    #[arg(short, long, env = "RECALL_PRIVATE_KEY", hide_env_values = true)]
+    pub key: Option<String>,
+    /// The network to use (defaults to devnet)
+    #[arg(short, long, env = "RECALL_NETWORK")]
+    pub network: Option<Network>,
+    /// Send every operation to this bucket instead of the buckets in the trace.
+    /// Without it, recorded bucket creations create new buckets
+    #[arg(short = 'b', long, value_parser = recall_provider::util::parse_address)]
+    pub bucket: Option<Address>,
+    /// The target to replay against.
+    #[arg(long, default_value = "sdk")]
+    pub target: Target,
+    #[command(flatten)]
+    pub s3: S3Config,
+    /// Broadcast mode to use for uploads
+    #[arg(long, default_value = "commit")]
+    pub broadcast: Broadcast,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -51,6 +135,8 @@ pub struct CleanupOpts {
     /// If the test targets the SDK or S3 client.
     #[arg(long, default_value = "sdk")]
     pub target: Target,
+    #[command(flatten)]
+    pub s3: S3Config,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -72,6 +158,8 @@ pub struct QueryOpts {
     /// If the test targets the SDK or S3 client.
     #[arg(long, default_value = "sdk")]
     pub target: Target,
+    #[command(flatten)]
+    pub s3: S3Config,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -101,6 +189,8 @@ pub struct BasicTestOpts {
     /// If the test targets the SDK or S3 client.
     #[arg(long, default_value = "sdk")]
     pub target: Target,
+    #[command(flatten)]
+    pub s3: S3Config,
     /// whether blobs should be deleted afterward
     #[arg(long, default_value = "false")]
     pub delete: bool,
@@ -109,12 +199,23 @@ pub struct BasicTestOpts {
     pub download: bool,
     #[arg(short = 'c', long, default_value = "100")]
     pub blob_cnt: u32,
+    /// upload for this many seconds instead of uploading --blob-cnt blobs
+    #[arg(long)]
+    pub duration: Option<u64>,
+    /// issue this many uploads per second across all accounts (open loop)
+    #[arg(long)]
+    pub rate: Option<f64>,
+    /// uploads each account keeps in flight at the same time
+    #[arg(long, default_value = "1")]
+    pub pipeline_depth: u32,
     /// blob size in bytes
     #[arg(short = 's', long, default_value = "1.0")]
     pub blob_size: i64,
     /// Broadcast mode to use for uploads/deletes
     #[arg(long, default_value = "commit")]
     pub broadcast: Broadcast,
+    #[command(flatten)]
+    pub outputs: OutputOpts,
 }
 
 impl From<BasicTestOpts> for TestConfig {
@@ -130,49 +231,307 @@ impl From<BasicTestOpts> for TestConfig {
                 upload: UploadTest {
                     bucket: opts.bucket,
                     blob_count: opts.blob_cnt,
+                    duration_secs: opts.duration,
+                    rate: opts.rate,
+                    pipeline_depth: opts.pipeline_depth,
                     prefix: opts.prefix,
                     blob_size: opts.blob_size,
                     overwrite: true,
                     broadcast_mode: opts.broadcast,
+                    layout: None,
                 },
                 download: Default::default(),
                 delete: opts.delete,
+                s3: opts.s3,
+                memory: Default::default(),
+                chaos: None,
+                assertions: Default::default(),
+                profile: Vec::new(),
+                mixed: None,
+                warmup: None,
+                resolve: None,
+                query: None,
+                inclusion: None,
+                circuit_breaker: None,
             },
         }
     }
 }
 
-pub async fn run(config: TestConfig) -> Result<()> {
-    let collector = Arc::new(Collector::new());
-    let tests = TestRunner::prepare(config, collector.clone()).await?;
-    let mut tasks = JoinSet::new();
-    for test in tests.into_iter() {
-        tasks.spawn(async move {
-            match test.execute().await {
-                Ok(_) => {}
-                Err(e) => {
-                    error!(error=?e, "Failed to run test");
-                }
+/// Context of errors that happen before any load is generated, e.g. while funding accounts
+#[derive(Debug)]
+pub struct SetupError;
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to setup")
+    }
+}
+
+/// Returned when only part of the work failed, e.g. some of the runners of a test run stopped
+/// with an error
+#[derive(Debug)]
+pub struct PartialFailure {
+    /// What failed, e.g. `test runners`
+    pub what: &'static str,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} {} failed", self.failed, self.total, self.what)
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+/// Returned by a test run that was stopped by Ctrl-C or SIGTERM, after its partial results
+/// were reported
+#[derive(Debug)]
+pub struct Interrupted {
+    /// The first signal the run received
+    pub signal: Signal,
+}
+
+/// Signal that stops a test run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Ctrl-C or SIGINT
+    Interrupt,
+    /// SIGTERM
+    Terminate,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "test run was interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Returned by a test run that the circuit breaker stopped early, after its partial results
+/// were reported
+#[derive(Debug)]
+pub struct CircuitBreakerTripped {
+    pub reason: String,
+}
+
+impl fmt::Display for CircuitBreakerTripped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit breaker stopped the test run: {}", self.reason)
+    }
+}
+
+impl std::error::Error for CircuitBreakerTripped {}
+
+/// Resolves on the next Ctrl-C or SIGTERM
+async fn shutdown_signal() -> std::io::Result<Signal> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| Signal::Interrupt),
+            _ = terminate.recv() => Ok(Signal::Terminate),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.map(|_| Signal::Interrupt)
+}
+
+/// Cancels `stop` on the first signal and keeps it in `received`, so the runners wind down
+/// and the results so far are still reported. A second signal exits right away, e.g. while
+/// blobs are being deleted
+fn stop_on_signal(stop: CancellationToken, received: Arc<OnceLock<Signal>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let signal = match shutdown_signal().await {
+            Ok(signal) => signal,
+            Err(error) => {
+                error!(?error, "failed to listen for signals");
+                return;
             }
-        });
+        };
+        warn!(
+            ?signal,
+            "interrupted, stopping the test runners. Interrupt again to exit right away"
+        );
+        let _ = received.set(signal);
+        stop.cancel();
+        if let Ok(signal) = shutdown_signal().await {
+            // what a shell reports for a process killed by the signal
+            std::process::exit(match signal {
+                Signal::Interrupt => 130,
+                Signal::Terminate => 143,
+            });
+        }
+    })
+}
+
+pub async fn run(config: TestConfig, outputs: OutputOpts) -> Result<()> {
+    config.validate().context(SetupError)?;
+    let mut metadata = RunMetadata::start(&config)?;
+    let assertions = config.test.assertions.clone();
+    let upload = config.test.upload.clone();
+    let profile = config.test.profile.clone();
+    let interrupt = CancellationToken::new();
+    // the circuit breaker stops the runners as well, without counting as an interruption
+    let stop = interrupt.child_token();
+    let collector = Arc::new(match config.test.circuit_breaker.clone() {
+        Some(breaker) => Collector::with_breaker(Breaker::new(breaker, stop.clone())),
+        None => Collector::new(),
+    });
+    let recorder = match &outputs.record {
+        Some(path) => Some(Arc::new(
+            TraceRecorder::create(path)
+                .await
+                .context("failed to create trace file")?,
+        )),
+        None => None,
+    };
+    let accounts = config.test.num_accounts;
+    let tests = TestRunner::prepare(config, collector.clone(), recorder.clone())
+        .await
+        .context(SetupError)?
+        .into_iter()
+        .map(|test| test.with_stop(stop.clone()))
+        .collect::<Vec<_>>();
+    // accounts that couldn't be funded or found on chain are skipped
+    if tests.is_empty() {
+        let err = anyhow!("none of the {accounts} accounts could be set up");
+        return Err(err.context(SetupError));
+    }
+    let runners = tests.len();
+    // installed after the setup, a signal before that just ends the process
+    let signal = Arc::new(OnceLock::new());
+    let signals = stop_on_signal(interrupt.clone(), signal.clone());
+    let schedule = if !profile.is_empty() {
+        Some(Schedule::Profile(&profile))
+    } else {
+        upload.rate.map(|rate| Schedule::Rate {
+            upload: &upload,
+            rate,
+        })
+    };
+    let (results, schedule) = match schedule {
+        Some(schedule) => scheduler::run_scheduled(tests, schedule, &stop).await?,
+        None => {
+            let mut tasks = JoinSet::new();
+            for test in tests.into_iter() {
+                tasks.spawn(async move {
+                    match test.execute().await {
+                        Ok(_) => true,
+                        Err(e) => {
+                            error!(error=?e, "Failed to run test");
+                            false
+                        }
+                    }
+                });
+            }
+            (tasks.join_all().await, Vec::new())
+        }
+    };
+    let failed_runners = results.into_iter().filter(|ok| !ok).count();
+    signals.abort();
+    metadata.finish();
+    metadata.interrupted = interrupt.is_cancelled();
+    if metadata.interrupted {
+        warn!("test run was interrupted, the results only cover part of it");
     }
-    tasks.join_all().await;
 
+    let mut failures = Vec::new();
+    let mut tripped = None;
+    let mut checked = false;
     if let Ok(mut collector) = Arc::try_unwrap(collector) {
+        checked = true;
         collector.close().await;
-        collector.display_aggregated()
-    } else {
-        error!("collector is still referenced");
+        collector.display_aggregated();
+        for summary in &schedule {
+            summary.display();
+        }
+        tripped = collector.tripped();
+        if let Some(reason) = &tripped {
+            error!("circuit breaker stopped the test run early: {reason}");
+        }
+        metadata.circuit_breaker = tripped.clone();
+        let summaries = collector.summaries();
+        failures = assertions::check(&assertions, &summaries);
+        for failure in &failures {
+            error!("assertion failed: {failure}");
+        }
+        if let Some(path) = &outputs.results_out {
+            let report = RunReport {
+                metadata,
+                operations: summaries,
+                failed_assertions: failures.clone(),
+                schedule,
+            };
+            match report.write(path) {
+                Ok(_) => info!("wrote results to {}", path.display()),
+                Err(error) => error!(?error, "failed to write results"),
+            }
+        }
+        if let Some(path) = &outputs.ops_out {
+            match write_operations(path, outputs.ops_format, &collector.get_operations()) {
+                Ok(_) => info!("wrote operations to {}", path.display()),
+                Err(error) => error!(?error, "failed to write operations"),
+            }
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        match Arc::try_unwrap(recorder) {
+            Ok(mut recorder) => {
+                if let Err(error) = recorder.close().await {
+                    error!(?error, "failed to write trace");
+                }
+            }
+            Err(_) => error!("trace recorder is still referenced"),
+        }
     }
 
+    // without the collector, nothing was reported or checked against the assertions
+    if !checked {
+        bail!("collector is still referenced, the results weren't reported");
+    }
+    if let Some(&signal) = signal.get() {
+        return Err(Interrupted { signal }.into());
+    }
+    if let Some(reason) = tripped {
+        return Err(CircuitBreakerTripped { reason }.into());
+    }
+    if !failures.is_empty() {
+        return Err(AssertionError { failures }.into());
+    }
+    if failed_runners > 0 {
+        return Err(PartialFailure {
+            what: "test runners",
+            failed: failed_runners,
+            total: runners,
+        }
+        .into());
+    }
     Ok(())
 }
 
 pub(crate) async fn setup_provider_wallet_bucket(
-    key: KeyData,
+    key: &KeyData,
     network: Network,
     bucket: Address,
 ) -> anyhow::Result<(JsonRpcProvider, Wallet, Bucket)> {
+    let (provider, wallet) = setup_provider_wallet(key, network).await?;
+
+    let machine = Bucket::attach(bucket).await.unwrap();
+    info!("using existing machine as bucket: {}", machine.address());
+
+    Ok((provider, wallet, machine))
+}
+
+pub(crate) async fn setup_provider_wallet(
+    key: &KeyData,
+    network: Network,
+) -> anyhow::Result<(JsonRpcProvider, Wallet)> {
     let network_cfg = network.get_config();
     let obj_api = network_cfg.object_api_url;
     info!("using network '{network}' and object api: {obj_api}");
@@ -180,7 +539,8 @@ pub(crate) async fn setup_provider_wallet_bucket(
     let provider = JsonRpcProvider::new_http(network_cfg.rpc_url, network.chain_id(), None, Some(obj_api))?;
 
     // Setup local wallet using private key from arg
-    let mut wallet = Wallet::new_secp256k1(key.sk, AccountKind::Ethereum, network_cfg.subnet_id)?;
+    let mut wallet =
+        Wallet::new_secp256k1(key.sk.clone(), AccountKind::Ethereum, network_cfg.subnet_id)?;
     wallet.init_sequence(&provider).await?;
     info!(
         "signer with address: {} on subnet id: {:?} ",
@@ -188,10 +548,44 @@ pub(crate) async fn setup_provider_wallet_bucket(
         wallet.subnet_id()
     );
 
-    let machine = Bucket::attach(bucket).await.unwrap();
-    info!("using existing machine as bucket: {}", machine.address());
+    Ok((provider, wallet))
+}
 
-    Ok((provider, wallet, machine))
+/// Builds the target an existing account runs its operations against. The HTTP target
+/// records its phases to the collector of `http_phases`, under its thread id
+pub(crate) fn account_target(
+    target: Target,
+    provider: &JsonRpcProvider,
+    wallet: &Wallet,
+    sk: &SecretKey,
+    network: Network,
+    s3: &S3Config,
+    http_phases: Option<(Arc<Collector>, String)>,
+) -> Result<Arc<dyn crate::targets::Target>> {
+    let (collector, thread_id) = match http_phases {
+        Some((collector, thread_id)) => (Some(collector), thread_id),
+        None => (None, wallet.address().to_string()),
+    };
+    let network_cfg = network.get_config();
+    let sdk = SdkTarget {
+        provider: provider.clone(),
+        wallet: wallet.clone(),
+        sk: sk.clone(),
+        subnet_id: network_cfg.subnet_id,
+        rpc_url: network_cfg.rpc_url.to_string().parse()?,
+        client: HttpClient::new()?,
+    };
+    Ok(match target {
+        Target::Sdk => Arc::new(sdk),
+        Target::S3 => Arc::new(S3Target::new(s3)?),
+        Target::Http => Arc::new(HttpTarget::new(
+            network.random_objects_api_url().to_string().parse()?,
+            sdk,
+            collector,
+            thread_id,
+        )?),
+        Target::Memory => bail!("the memory target is only available for test runs"),
+    })
 }
 
 pub(crate) async fn list_bucket_items(
@@ -203,17 +597,62 @@ pub(crate) async fn list_bucket_items(
     let mut results = Vec::new();
 
     let start = Instant::now();
-    let (mut list, mut next_key) = target.list_objects(bucket, prefix, None).await?;
+    let (mut list, mut next_key) = target.list_objects(bucket, prefix, None, None).await?;
     query_durations.push(start.elapsed());
     debug!(?list, "queried objects");
     results.extend(list);
 
     while let Some(start_key) = next_key {
         let start = Instant::now();
-        (list, next_key) = target.list_objects(bucket, prefix, Some(start_key)).await?;
+        (list, next_key) = target
+            .list_objects(bucket, prefix, Some(start_key), None)
+            .await?;
         query_durations.push(start.elapsed());
 
         results.extend(list);
     }
     Ok((results, query_durations))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::runner::tests::memory_config;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_run_rejects_invalid_circuit_breakers() {
+        for (breaker, expected) in [
+            (
+                json!({}),
+                "the circuit breaker needs a maxErrorRate or maxConsecutiveErrors",
+            ),
+            (
+                json!({ "maxConsecutiveErrors": 0 }),
+                "the circuit breaker's maxConsecutiveErrors must be at least 1",
+            ),
+            (
+                json!({ "maxErrorRate": 1.5 }),
+                "the circuit breaker's maxErrorRate must be between 0.0 and 1.0",
+            ),
+            (
+                json!({ "maxErrorRate": -0.1 }),
+                "the circuit breaker's maxErrorRate must be between 0.0 and 1.0",
+            ),
+            (
+                json!({ "maxErrorRate": 0.5, "window": 0 }),
+                "the circuit breaker's window must be at least 1",
+            ),
+        ] {
+            let config = memory_config(json!({
+                "numAccounts": 1,
+                "upload": { "blobCount": 5, "prefix": "foo", "blobSize": 64 },
+                "delete": true,
+                "circuitBreaker": breaker,
+            }));
+            let err = run(config, OutputOpts::default()).await.unwrap_err();
+            assert!(err.downcast_ref::<SetupError>().is_some());
+            assert_eq!(expected, err.root_cause().to_string());
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use chrono::Utc;
+use recall_provider::util::parse_address;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use recall_sdk::network::Network;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+use super::{account_target, setup_provider_wallet, ReplayOpts, SetupError};
+use crate::config::{Broadcast, Target as ConfigTarget};
+use crate::parse_private_key;
+use crate::payload::RandomReader;
+use crate::stats::collector::Collector;
+use crate::stats::ops::{Operation, OperationType};
+use crate::targets::memory::InMemoryTarget;
+use crate::targets::recorder::{TraceEntry, TraceOp};
+use crate::targets::{Payload, Target};
+
+pub async fn replay(opts: ReplayOpts) -> Result<()> {
+    let entries = read_trace(&opts.trace).await?;
+    info!(
+        "replaying {} operations from {:?}",
+        entries.len(),
+        opts.trace
+    );
+
+    let network = opts.network.unwrap_or(Network::Devnet);
+    let target: Arc<dyn Target> = match opts.target {
+        ConfigTarget::Memory => Arc::new(InMemoryTarget::default()),
+        target => {
+            let Some(key) = &opts.key else {
+                bail!("a private key is required to replay against {target:?}");
+            };
+            let key = parse_private_key(key)?;
+            let (provider, wallet) = setup_provider_wallet(&key, network)
+                .await
+                .context(SetupError)?;
+            account_target(target, &provider, &wallet, &key.sk, network, &opts.s3, None)?
+        }
+    };
+
+    let fixed_bucket = match opts.bucket {
+        Some(address) => Some(Arc::new(Bucket::attach(address).await?)),
+        None => None,
+    };
+
+    let collector = Arc::new(Collector::new());
+    replay_entries(
+        target,
+        collector.clone(),
+        entries,
+        fixed_bucket,
+        opts.speed,
+        opts.broadcast,
+    )
+    .await?;
+
+    if let Ok(mut collector) = Arc::try_unwrap(collector) {
+        collector.close().await;
+        collector.display_aggregated()
+    } else {
+        error!("collector is still referenced");
+    }
+
+    Ok(())
+}
+
+/// Reads the entries of a trace file, ordered by their offset
+async fn read_trace(path: &Path) -> Result<Vec<TraceEntry>> {
+    let trace = tokio::fs::read_to_string(path)
+        .await
+        .context("failed to read trace")?;
+    let mut entries = trace
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str::<TraceEntry>)
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to parse trace")?;
+    entries.sort_by_key(|entry| entry.offset_ms);
+    Ok(entries)
+}
+
+/// Plays `entries` back against `target`, `speed` times faster than they were recorded or as
+/// fast as possible if it is 0
+async fn replay_entries(
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    entries: Vec<TraceEntry>,
+    fixed_bucket: Option<Arc<Bucket>>,
+    speed: f64,
+    broadcast_mode: Broadcast,
+) -> Result<()> {
+    // buckets from the trace, mapped to the buckets used for the replay
+    let mut buckets: HashMap<String, Arc<Bucket>> = HashMap::new();
+
+    let start = tokio::time::Instant::now();
+    let mut tasks = JoinSet::new();
+    for entry in entries {
+        if speed > 0.0 {
+            let offset = Duration::from_secs_f64(entry.offset_ms as f64 / 1000.0 / speed);
+            tokio::time::sleep_until(start + offset).await;
+        }
+
+        let bucket = match (&fixed_bucket, entry.op) {
+            (Some(bucket), _) => bucket.clone(),
+            (None, TraceOp::CreateBucket) => {
+                let bucket = Arc::new(target.create_bucket().await?);
+                info!(
+                    "created bucket {} in place of {}",
+                    bucket.address(),
+                    entry.bucket
+                );
+                buckets.insert(entry.bucket, bucket);
+                continue;
+            }
+            (None, _) => match buckets.get(&entry.bucket) {
+                Some(bucket) => bucket.clone(),
+                None => {
+                    let bucket = Arc::new(Bucket::attach(parse_address(&entry.bucket)?).await?);
+                    buckets.insert(entry.bucket.clone(), bucket.clone());
+                    bucket
+                }
+            },
+        };
+        if entry.op == TraceOp::CreateBucket {
+            continue;
+        }
+
+        let target = target.clone();
+        let collector = collector.clone();
+        tasks.spawn(async move {
+            let key = entry.key.clone();
+            if let Err(err) = replay_entry(target, collector, &bucket, entry, broadcast_mode).await
+            {
+                error!(error=?err, %key, "failed to replay operation");
+            }
+        });
+    }
+    tasks.join_all().await;
+    info!(elapsed=?start.elapsed(), "replay finished");
+    Ok(())
+}
+
+async fn replay_entry(
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    bucket: &Bucket,
+    entry: TraceEntry,
+    broadcast_mode: Broadcast,
+) -> Result<()> {
+    let size = entry.size.unwrap_or_default() as i64;
+    let op_type = match entry.op {
+        TraceOp::Put => OperationType::Put,
+        TraceOp::Get => OperationType::Get,
+        TraceOp::List => OperationType::List,
+        TraceOp::Delete => OperationType::Delete,
+        TraceOp::CreateBucket => return Ok(()),
+    };
+    let mut operation = Operation {
+        id: entry.account.clone(),
+        start: Utc::now(),
+        op_type,
+        size,
+        file: entry.key.clone(),
+        ..Default::default()
+    };
+    let result = match entry.op {
+        TraceOp::Put => target
+            .add_object(
+                bucket,
+                &entry.key,
+                Payload::new(RandomReader::new(size as u64), size as u64),
+                HashMap::new(),
+                true,
+                broadcast_mode,
+            )
+            .await
+            .map(|_| ()),
+        TraceOp::Get => {
+            target
+                .get_object(bucket, &entry.key, Box::new(tokio::io::sink()), entry.range)
+                .await
+        }
+        TraceOp::List => {
+            let start_key = entry.start_key.map(String::into_bytes);
+            match &entry.delimiter {
+                Some(delimiter) => target
+                    .list_directory(bucket, &entry.key, delimiter, start_key, entry.limit)
+                    .await
+                    .map(|_| ()),
+                None => target
+                    .list_objects(bucket, &entry.key, start_key, entry.limit)
+                    .await
+                    .map(|_| ()),
+            }
+        }
+        TraceOp::Delete => target.delete_object(bucket, &entry.key).await,
+        TraceOp::CreateBucket => unreachable!("create bucket is handled by the caller"),
+    };
+    operation.end = Utc::now();
+
+    if let Err(err) = &result {
+        operation.error = err.to_string();
+        if matches!(entry.op, TraceOp::Put | TraceOp::Delete) {
+            target.resync().await?;
+        }
+    }
+    collector.collect(operation).await?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::recorder::{RecordingTarget, TraceRecorder};
+    use serde_json::json;
+
+    /// A trace entry without what differs between a recording and its replay
+    fn replayed_part(entry: &TraceEntry) -> serde_json::Value {
+        let mut value = serde_json::to_value(entry).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("offsetMs");
+        fields.remove("bucket");
+        value
+    }
+
+    #[tokio::test]
+    async fn test_replays_a_recorded_trace() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("recall-loader-trace-{}.jsonl", std::process::id()));
+        let replayed_path = path.with_extension("replayed.jsonl");
+
+        let recorder = Arc::new(TraceRecorder::create(&path).await.unwrap());
+        let target = RecordingTarget::wrap(
+            Arc::new(InMemoryTarget::default()),
+            &Some(recorder.clone()),
+            "1".to_string(),
+        );
+        let bucket = target.create_bucket().await.unwrap();
+        for key in ["foo/1", "foo/2", "foo/bar/3"] {
+            let payload = Payload::new(std::io::Cursor::new(vec![7; 16]), 16);
+            target
+                .add_object(
+                    &bucket,
+                    key,
+                    payload,
+                    HashMap::new(),
+                    true,
+                    Broadcast::Commit,
+                )
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        target
+            .get_object(
+                &bucket,
+                "foo/1",
+                Box::new(tokio::io::sink()),
+                Some("0-7".to_string()),
+            )
+            .await
+            .unwrap();
+        target
+            .list_directory(&bucket, "foo/", "/", Some(b"foo/1".to_vec()), Some(1))
+            .await
+            .unwrap();
+        target
+            .list_objects(&bucket, "foo/", None, Some(2))
+            .await
+            .unwrap();
+        target.delete_object(&bucket, "foo/2").await.unwrap();
+        drop(target);
+        let mut recorder = Arc::try_unwrap(recorder).ok().unwrap();
+        recorder.close().await.unwrap();
+
+        let trace = tokio::fs::read_to_string(&path).await.unwrap();
+        let lines: Vec<serde_json::Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(8, lines.len());
+        let address = bucket.address().to_string();
+        let offsets: Vec<u64> = lines
+            .iter()
+            .map(|line| line["offsetMs"].as_u64().unwrap())
+            .collect();
+        assert_eq!(
+            json!({
+                "offsetMs": offsets[0],
+                "account": "1",
+                "op": "createBucket",
+                "bucket": address,
+                "key": "",
+            }),
+            lines[0]
+        );
+        assert_eq!(
+            json!({
+                "offsetMs": offsets[1],
+                "account": "1",
+                "op": "put",
+                "bucket": address,
+                "key": "foo/1",
+                "size": 16,
+            }),
+            lines[1]
+        );
+        assert_eq!(
+            json!({
+                "offsetMs": offsets[4],
+                "account": "1",
+                "op": "get",
+                "bucket": address,
+                "key": "foo/1",
+                "range": "0-7",
+            }),
+            lines[4]
+        );
+        assert_eq!(
+            json!({
+                "offsetMs": offsets[5],
+                "account": "1",
+                "op": "list",
+                "bucket": address,
+                "key": "foo/",
+                "startKey": "foo/1",
+                "limit": 1,
+                "delimiter": "/",
+            }),
+            lines[5]
+        );
+        // offsets count from the start of the recording
+        assert!(offsets[0] < 1000, "{offsets:?}");
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(offsets[4] - offsets[3] >= 100, "{offsets:?}");
+
+        // replay four times faster while recording what the replay does
+        let entries = read_trace(&path).await.unwrap();
+        let recorder = Arc::new(TraceRecorder::create(&replayed_path).await.unwrap());
+        let target = RecordingTarget::wrap(
+            Arc::new(InMemoryTarget::default()),
+            &Some(recorder.clone()),
+            "1".to_string(),
+        );
+        let collector = Arc::new(Collector::new());
+        tokio::time::pause();
+        let start = tokio::time::Instant::now();
+        replay_entries(
+            target,
+            collector.clone(),
+            entries.clone(),
+            None,
+            4.0,
+            Broadcast::Commit,
+        )
+        .await
+        .unwrap();
+        let elapsed = start.elapsed();
+        tokio::time::resume();
+        let expected = Duration::from_secs_f64(offsets[7] as f64 / 1000.0 / 4.0);
+        assert!(
+            elapsed >= expected && elapsed < expected + Duration::from_millis(2),
+            "replay took {elapsed:?}, expected {expected:?}"
+        );
+
+        let mut recorder = Arc::try_unwrap(recorder).ok().unwrap();
+        recorder.close().await.unwrap();
+        let replayed = read_trace(&replayed_path).await.unwrap();
+        assert_eq!(
+            entries.iter().map(replayed_part).collect::<Vec<_>>(),
+            replayed.iter().map(replayed_part).collect::<Vec<_>>()
+        );
+
+        let mut collector = Arc::try_unwrap(collector).ok().unwrap();
+        collector.close().await;
+        let ops = collector.get_operations();
+        assert_eq!(7, ops.len());
+        assert!(ops.iter().all(|op| op.error.is_empty()));
+        let lists = ops
+            .iter()
+            .filter(|op| op.op_type == OperationType::List)
+            .count();
+        assert_eq!(2, lists);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        tokio::fs::remove_file(&replayed_path).await.unwrap();
+    }
+}
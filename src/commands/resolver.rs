@@ -0,0 +1,245 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use recall_provider::fvm_shared::address::Address;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::config::ResolveConfig;
+use crate::payload::{Digest, HashingWriter, StreamHash};
+use crate::stats::collector::Collector;
+use crate::stats::ops::{Operation, OperationType};
+use crate::stats::warmup::Warmup;
+use crate::targets::Target;
+
+/// Polls every upload of a runner until it can be downloaded and records the time from its
+/// commit to the first successful download as a `Resolve` operation
+pub struct Resolver {
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    thread_id: String,
+    config: ResolveConfig,
+    permits: Arc<Semaphore>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+/// An upload that was committed and has to be resolved
+pub struct Committed {
+    pub bucket: Address,
+    pub key: String,
+    pub at: DateTime<Utc>,
+    pub warmup: bool,
+    /// Digest of the uploaded content, checked against the first successful download
+    pub digest: Option<Digest>,
+}
+
+impl Resolver {
+    pub fn new(
+        target: Arc<dyn Target>,
+        collector: Arc<Collector>,
+        thread_id: String,
+        config: ResolveConfig,
+    ) -> Self {
+        Self {
+            target,
+            collector,
+            thread_id,
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            config,
+            tasks: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    /// Starts polling a committed upload in the background
+    pub fn watch(&self, committed: Committed) {
+        let watched = Instant::now();
+        let target = self.target.clone();
+        let collector = self.collector.clone();
+        let thread_id = self.thread_id.clone();
+        let config = self.config.clone();
+        let permits = self.permits.clone();
+        self.tasks.lock().unwrap().spawn(async move {
+            let key = committed.key.clone();
+            let polls = Polls {
+                watched,
+                config: &config,
+                permits: &permits,
+            };
+            if let Err(error) = resolve(target, collector, thread_id, committed, polls).await {
+                error!(?error, %key, "failed to resolve blob");
+            }
+        });
+    }
+
+    /// Waits until every watched upload was resolved or timed out. Stops polling the rest
+    /// once `stop` is cancelled
+    pub async fn wait(&self, stop: &CancellationToken) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        tokio::select! {
+            _ = async { while tasks.join_next().await.is_some() {} } => {}
+            _ = stop.cancelled() => {}
+        }
+        tasks.shutdown().await;
+    }
+}
+
+/// How one upload is polled
+struct Polls<'a> {
+    /// When the upload was watched, right after its commit. Its resolve time is measured on
+    /// the runtime clock from here
+    watched: Instant,
+    config: &'a ResolveConfig,
+    /// Held by the polls in flight. A poll that finds none free waits for the next interval
+    /// instead of queueing, so a blob is never waiting on other blobs while it could
+    /// already be downloaded
+    permits: &'a Semaphore,
+}
+
+async fn resolve(
+    target: Arc<dyn Target>,
+    collector: Arc<Collector>,
+    thread_id: String,
+    committed: Committed,
+    polls: Polls<'_>,
+) -> Result<()> {
+    let config = polls.config;
+    let bucket = Bucket::attach(committed.bucket).await?;
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut operation = Operation {
+        id: thread_id,
+        start: committed.at,
+        op_type: OperationType::Resolve,
+        file: committed.key.clone(),
+        warmup: committed.warmup,
+        ..Default::default()
+    };
+
+    loop {
+        let hash = StreamHash::default();
+        let result = match polls.permits.try_acquire() {
+            Ok(_permit) => {
+                let writer = HashingWriter::new(tokio::io::sink(), hash.clone());
+                let get = target.get_object(&bucket, &committed.key, Box::new(writer), None);
+                Some(Warmup::scope(committed.warmup, get).await)
+            }
+            Err(_) => None,
+        };
+        let elapsed = polls.watched.elapsed();
+        operation.end = committed.at + chrono::Duration::from_std(elapsed)?;
+        match result {
+            Some(Ok(_)) => {
+                if let Some(digest) = &committed.digest {
+                    if let Err(err) = hash.verify(&committed.key, digest) {
+                        // resolved to the wrong content, polling won't fix that
+                        operation.error = err.to_string();
+                        operation.corrupted = true;
+                        break;
+                    }
+                }
+                debug!(key = committed.key, time = ?operation.duration(), "resolved");
+                break;
+            }
+            Some(Err(err)) if elapsed >= timeout => {
+                operation.error = format!("not resolved within {}ms: {err}", config.timeout_ms);
+                break;
+            }
+            None if elapsed >= timeout => {
+                operation.error = format!(
+                    "not resolved within {}ms: no free poll of {}",
+                    config.timeout_ms, config.concurrency
+                );
+                break;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(config.poll_interval_ms)).await,
+        }
+    }
+    collector.collect(operation).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Broadcast, MemoryConfig};
+    use crate::targets::memory::InMemoryTarget;
+    use crate::targets::Payload;
+    use std::collections::HashMap;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resolves_every_upload() {
+        let memory = MemoryConfig {
+            resolve_delay_ms: 200,
+            ..Default::default()
+        };
+        let target = Arc::new(InMemoryTarget::new(memory));
+        let bucket = target.create_bucket().await.unwrap();
+        let collector = Arc::new(Collector::new());
+        let config = ResolveConfig {
+            poll_interval_ms: 20,
+            timeout_ms: 500,
+            concurrency: 1,
+        };
+        let resolver = Resolver::new(target.clone(), collector.clone(), "1".to_string(), config);
+
+        // never uploaded, so it times out. The uploads after it don't wait for it to give up
+        resolver.watch(Committed {
+            bucket: bucket.address(),
+            key: "foo/4".to_string(),
+            at: Utc::now(),
+            warmup: false,
+            digest: None,
+        });
+        for key in ["foo/1", "foo/2", "foo/3"] {
+            target
+                .add_object(
+                    &bucket,
+                    key,
+                    Payload::new(std::io::Cursor::new(vec![7; 64]), 64),
+                    HashMap::new(),
+                    true,
+                    Broadcast::Commit,
+                )
+                .await
+                .unwrap();
+            resolver.watch(Committed {
+                bucket: bucket.address(),
+                key: key.to_string(),
+                at: Utc::now(),
+                warmup: false,
+                digest: None,
+            });
+        }
+        // resolves to different content than was uploaded
+        resolver.watch(Committed {
+            bucket: bucket.address(),
+            key: "foo/3".to_string(),
+            at: Utc::now(),
+            warmup: false,
+            digest: Some([0; 32]),
+        });
+        resolver.wait(&CancellationToken::new()).await;
+        drop(resolver);
+
+        let mut collector = Arc::try_unwrap(collector).ok().unwrap();
+        collector.close().await;
+        let mut ops = collector.get_operations();
+        ops.sort_by(|a, b| (&a.file, a.corrupted).cmp(&(&b.file, b.corrupted)));
+        assert_eq!(5, ops.len());
+        for op in &ops[..3] {
+            assert_eq!(OperationType::Resolve, op.op_type);
+            assert!(op.error.is_empty(), "{}", op.error);
+            assert_eq!(200, op.duration().num_milliseconds(), "{}", op.file);
+        }
+        assert!(ops[3].corrupted);
+        assert!(ops[3].error.starts_with("integrity check failed for foo/3"));
+        assert!(ops[4].error.starts_with("not resolved within 500ms"));
+        assert_eq!(500, ops[4].duration().num_milliseconds());
+    }
+}
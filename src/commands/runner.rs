@@ -1,16 +1,30 @@
-use crate::commands::downloader::Downloader;
+use crate::commands::downloader::{download_blob, Blob, Downloader};
+use crate::commands::inclusion::{Broadcasted, InclusionTracker};
+use crate::commands::resolver::{Committed, Resolver};
+use crate::commands::{account_target, list_bucket_items};
 use crate::config::{
-    Broadcast, RandomizedNetwork, Target as ConfigTarget, TestConfig, TestRunConfig,
+    Broadcast, MixedWorkload, QueryTest, RandomizedNetwork, Target as ConfigTarget, TestConfig,
+    TestRunConfig,
 };
 use crate::funder::Funder;
+use crate::payload::{Digest, RandomReader};
 use crate::stats::collector::Collector;
 use crate::stats::ops::{Operation, OperationType};
-use crate::targets::sdk::SdkTarget;
-use crate::targets::Target;
+use crate::stats::warmup::Warmup;
+use crate::targets::chaos::ChaosTarget;
+use crate::targets::memory::InMemoryTarget;
+use crate::targets::recorder::{RecordingTarget, TraceRecorder};
+use crate::targets::sequencer::SequencedTarget;
+use crate::targets::{ListPage, Payload, Target};
 use crate::KeyData;
 use anyhow::{bail, Context as _, Result};
 use chrono::Utc;
 use ethers::types::H160;
+use futures::{future, stream, StreamExt as _, TryStreamExt as _};
+use rand::distributions::{Distribution as _, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom as _;
+use rand::{Rng as _, SeedableRng as _};
 use recall_provider::{
     fvm_shared::econ::TokenAmount,
     json_rpc::JsonRpcProvider
@@ -21,35 +35,266 @@ use recall_sdk::{
 };
 use recall_signer::key::random_secretkey;
 use recall_signer::{AccountKind, EthAddress, Signer as _, Wallet};
-use rand::{thread_rng, Rng as _};
-use std::sync::Arc;
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
-use tokio::io::AsyncWriteExt as _;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 pub struct TestRunner {
     target: Arc<dyn Target>,
-    provider: JsonRpcProvider,
     wallet: Wallet,
     collector: Arc<Collector>,
     test: TestRunConfig,
     thread_id: String,
+    warmup: Arc<Warmup>,
+    resolver: Option<Resolver>,
+    tracker: Option<InclusionTracker>,
+    /// Digests of the uploaded blobs that weren't deleted, to verify their downloads
+    digests: Mutex<HashMap<String, Digest>>,
+    /// Cancelled to wind the run down early. The operations in flight still finish and the
+    /// delete phase still runs on what was uploaded
+    stop: CancellationToken,
 }
 
 impl TestRunner {
+    /// Stops the runner once `stop` is cancelled
+    pub fn with_stop(mut self, stop: CancellationToken) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
     pub async fn execute(&self) -> Result<()> {
-        let upload_config = self.test.upload.clone();
-        let download_config = self.test.download.clone();
+        let bucket = self.bucket().await?;
+        if let Some(workload) = &self.test.mixed {
+            return self.execute_mixed(&bucket, workload).await;
+        }
+        if let Some(query) = &self.test.query {
+            return self.execute_query(&bucket, query).await;
+        }
 
-        let bucket = if let Some(bucket) = upload_config.bucket {
+        let upload_config = &self.test.upload;
+        let mut keys = Vec::with_capacity(upload_config.blob_count as usize);
+        let started = Instant::now();
+        let mut uploads = stream::iter(0..)
+            .take_while(|i| {
+                future::ready(!self.stopped() && upload_config.has_more(*i, started.elapsed()))
+            })
+            .map(|i| {
+                let key = self.key(i);
+                let bucket = &bucket;
+                async move {
+                    let uploaded = self.upload(bucket, &key, "").await?;
+                    anyhow::Ok(uploaded.then_some(key))
+                }
+            })
+            .buffer_unordered(upload_config.pipeline_depth.max(1) as usize);
+        while let Some(key) = uploads.try_next().await? {
+            keys.extend(key);
+        }
+
+        self.finish(&bucket, &keys).await
+    }
+
+    /// Uploads the initial key set, then runs the operations of the mixed `workload` on it.
+    /// What is left of the key set is deleted at the end if `delete` is set, without
+    /// counting towards the stats
+    async fn execute_mixed(&self, bucket: &Bucket, workload: &MixedWorkload) -> Result<()> {
+        let weights = workload.ratios.weights();
+        let choices = WeightedIndex::new(weights.iter().map(|(_, weight)| *weight))
+            .context("mixed workload needs at least one operation with a ratio")?;
+        let mut rng = StdRng::from_entropy();
+
+        let mut keys = Vec::with_capacity(self.test.upload.blob_count as usize);
+        let mut next_key = 0;
+        while next_key < self.test.upload.blob_count && !self.stopped() {
+            let key = self.key(next_key);
+            next_key += 1;
+            if self.upload(bucket, &key, "").await? {
+                keys.push(key);
+            }
+        }
+
+        let started = Instant::now();
+        let mut done = 0;
+        while !self.stopped() && workload.has_more(done, started.elapsed()) {
+            done += 1;
+            let mut op_type = weights[choices.sample(&mut rng)].0.clone();
+            if keys.is_empty() && matches!(op_type, OperationType::Get | OperationType::Delete) {
+                op_type = OperationType::Put;
+            }
+            match op_type {
+                OperationType::Get => {
+                    let key = keys.choose(&mut rng).expect("key set is not empty");
+                    // failed downloads are collected and logged by download_blob
+                    let _ = download_blob(
+                        self.target.clone(),
+                        self.collector.clone(),
+                        self.thread_id.clone(),
+                        bucket.address(),
+                        &self.blob(key),
+                        self.test.upload.blob_size,
+                        &self.warmup,
+                    )
+                    .await;
+                }
+                OperationType::Put => {
+                    let key = self.key(next_key);
+                    next_key += 1;
+                    if self.upload(bucket, &key, "").await? {
+                        keys.push(key);
+                    }
+                }
+                OperationType::List => {
+                    let _ = self.list_blobs(bucket).await;
+                }
+                OperationType::Delete => {
+                    let i = rng.gen_range(0..keys.len());
+                    if self.delete_blob(&keys[i], bucket).await.is_ok() {
+                        keys.swap_remove(i);
+                    } else {
+                        self.target.resync().await?;
+                    }
+                }
+                _ => unreachable!("only weighted operations are picked"),
+            }
+        }
+        if let Some(resolver) = &self.resolver {
+            resolver.wait(&self.stop).await;
+        }
+        if let Some(tracker) = &self.tracker {
+            tracker.wait(&self.stop).await;
+        }
+        info!(
+            %self.thread_id,
+            operations = done,
+            keys = keys.len(),
+            "finished mixed workload in {:?}",
+            started.elapsed()
+        );
+
+        if self.test.delete {
+            for key in &keys {
+                if let Err(error) = self.target.delete_object(bucket, key).await {
+                    warn!(?error, %key, "failed to clean up blob");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the keys of the listing benchmark that are missing from the bucket, then lists
+    /// all of them page by page, directory by directory if there is a delimiter. Every page
+    /// is a `List` operation in the stage of its page size. The uploaded keys are deleted at
+    /// the end if `delete` is set, without counting towards the stats
+    async fn execute_query(&self, bucket: &Bucket, query: &QueryTest) -> Result<()> {
+        let prefix = self.test.upload.get_key_with_prefix("");
+        let (existing, _) = list_bucket_items(self.target.clone(), bucket, &prefix)
+            .await
+            .context("failed to list existing keys")?;
+        let existing: HashSet<String> = existing.into_iter().collect();
+        let mut uploaded = Vec::new();
+        for i in (0..query.keys).take_while(|_| !self.stopped()) {
+            let key = self.key(i);
+            if !existing.contains(&key) && self.upload(bucket, &key, "").await? {
+                uploaded.push(key);
+            }
+        }
+        info!(
+            %self.thread_id,
+            existing = existing.len(),
+            uploaded = uploaded.len(),
+            "populated bucket {} for listing",
+            bucket.address()
+        );
+        if let Some(tracker) = &self.tracker {
+            tracker.wait(&self.stop).await;
+        }
+
+        for limit in query.page_sizes() {
+            let stage = match limit {
+                Some(limit) => format!("{limit} per page"),
+                None => "default page size".to_string(),
+            };
+            for _ in (0..query.passes.max(1)).take_while(|_| !self.stopped()) {
+                let started = Instant::now();
+                let (mut pages, mut keys) = (0, 0);
+                // directories left to list, only the prefix itself without a delimiter
+                let mut directories = VecDeque::from([prefix.clone()]);
+                while let Some(directory) = directories.pop_front().filter(|_| !self.stopped()) {
+                    let mut start_key = None;
+                    loop {
+                        // a failed page is recorded, the rest of the directory can't be
+                        // listed without it
+                        let Ok(page) = self
+                            .list_page(
+                                bucket,
+                                &directory,
+                                query.delimiter.as_deref(),
+                                start_key,
+                                limit,
+                                &stage,
+                            )
+                            .await
+                        else {
+                            break;
+                        };
+                        pages += 1;
+                        keys += page.keys.len();
+                        directories.extend(page.common_prefixes);
+                        start_key = page.next_key;
+                        if start_key.is_none() {
+                            break;
+                        }
+                    }
+                }
+                info!(
+                    %self.thread_id,
+                    stage,
+                    pages,
+                    keys,
+                    "listed bucket in {:?}",
+                    started.elapsed()
+                );
+            }
+        }
+
+        if self.test.delete {
+            for key in &uploaded {
+                if let Err(error) = self.target.delete_object(bucket, key).await {
+                    warn!(?error, %key, "failed to clean up blob");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Key of the `i`-th blob this runner uploads
+    pub fn key(&self, i: u32) -> String {
+        self.test.upload.key(i)
+    }
+
+    /// A blob this runner uploaded, with the digest of its content
+    fn blob(&self, key: &str) -> Blob {
+        Blob {
+            key: key.to_string(),
+            digest: self.digests.lock().unwrap().get(key).copied(),
+        }
+    }
+
+    /// Attaches the configured bucket or creates a new one
+    pub async fn bucket(&self) -> Result<Bucket> {
+        if let Some(bucket) = self.test.upload.bucket {
             let bucket = Bucket::attach(bucket)
                 .await
                 .context("failed to attach bucket")?;
             info!(%self.thread_id, "using existing machine as bucket: {}", bucket.address());
-            bucket
+            Ok(bucket)
         } else {
             let bucket = self.target.clone().create_bucket().await?;
             info!(
@@ -58,62 +303,104 @@ impl TestRunner {
                 "created new bucket {}",
                 bucket.address(),
             );
-            bucket
-        };
-
-        let mut keys = Vec::with_capacity(upload_config.blob_count as usize);
-        for i in 0..upload_config.blob_count {
-            let key = self.test.upload.get_key_with_prefix(&i.to_string());
-
-            if self
-                .upload_blob(
-                    &bucket,
-                    &key,
-                    upload_config.blob_size_bytes(),
-                    upload_config.broadcast_mode,
-                    upload_config.overwrite,
-                )
-                .await
-                .is_err()
-            {
-                // need to revert the sequence number since it was incremented by the sdk but failed
-                // TODO: update SDK to be nicer here
-                self.wallet.clone().init_sequence(&self.provider).await?;
-                continue;
-            }
+            Ok(bucket)
+        }
+    }
 
-            keys.push(key.clone());
+    /// Uploads one blob and returns whether it succeeded. Only fails if the target can't
+    /// recover from the failed upload. The operation is tagged with the profile `stage`
+    pub async fn upload(&self, bucket: &Bucket, key: &str, stage: &str) -> Result<bool> {
+        let upload_config = &self.test.upload;
+        if self
+            .upload_blob(
+                bucket,
+                key,
+                upload_config.blob_size_bytes(),
+                upload_config.broadcast_mode,
+                upload_config.overwrite,
+                stage,
+            )
+            .await
+            .is_err()
+        {
+            // need to revert the sequence number since it was incremented by the sdk but failed
+            self.target.resync().await?;
+            return Ok(false);
         }
+        Ok(true)
+    }
 
+    /// Runs the download and delete phases on the uploaded `keys`
+    pub async fn finish(&self, bucket: &Bucket, keys: &[String]) -> Result<()> {
         if keys.is_empty() {
+            if self.stopped() {
+                return Ok(());
+            }
             error!(%self.thread_id,"failed to upload any blobs");
             bail!("{} failed to upload blobs", self.thread_id);
         }
 
-        if let Some(config) = download_config {
-            loop_until_blob_found(&keys, self.target.clone(), &bucket, 10).await;
+        if let Some(resolver) = &self.resolver {
+            info!(
+                "waiting for network to resolve objects in bucket {}...",
+                bucket.address()
+            );
+            resolver.wait(&self.stop).await;
+        } else if self.test.download.is_some() && !self.stopped() {
+            tokio::select! {
+                _ = loop_until_blob_found(keys, self.target.clone(), bucket, 10) => {}
+                _ = self.stop.cancelled() => {}
+            }
+        }
+        if let Some(tracker) = &self.tracker {
+            info!("waiting for upload transactions to be included...");
+            tracker.wait(&self.stop).await;
+        }
+
+        if let Some(config) = self.test.download.as_ref().filter(|_| !self.stopped()) {
             let mut downloader = Downloader::new(
                 self.target.clone(),
                 self.collector.clone(),
                 self.thread_id.clone(),
                 bucket.address(),
                 config.concurrency(),
-                upload_config.blob_size,
+                self.test.upload.blob_size,
+                self.warmup.clone(),
             );
-            downloader.download(&keys).await?;
-            downloader.close().await;
+            let picked = config.pick(keys.len(), &mut StdRng::from_entropy())?;
+            let blobs = picked.into_iter().map(|i| self.blob(&keys[i])).collect();
+            let downloads = async {
+                downloader.download(blobs).await?;
+                downloader.close().await;
+                anyhow::Ok(())
+            };
+            tokio::select! {
+                result = downloads => result?,
+                _ = self.stop.cancelled() => downloader.abort().await,
+            }
         }
 
         if self.test.delete {
-            for key in &keys {
-                self.delete_blob(key, &bucket).await?;
+            if self.stopped() {
+                info!(%self.thread_id, "deleting {} blobs of the stopped run", keys.len());
+            }
+            for key in keys {
+                self.delete_blob(key, bucket).await?;
             }
         }
 
         Ok(())
     }
 
-    pub async fn prepare(config: TestConfig, collector: Arc<Collector>) -> Result<Vec<Self>> {
+    pub async fn prepare(
+        config: TestConfig,
+        collector: Arc<Collector>,
+        recorder: Option<Arc<TraceRecorder>>,
+    ) -> Result<Vec<Self>> {
+        if let ConfigTarget::Memory = config.test.target {
+            return Self::prepare_in_memory(config, collector, recorder);
+        }
+
         let network = config.network;
         let network_cfg = network.get_config();
         info!("using network '{network}'");
@@ -127,7 +414,7 @@ impl TestRunner {
         )
         .context("failed to setup json provider")?;
         // reuse wallets because we can't have multiple due to msg/actor sequence numbers getting out of sync
-        // this wallet won't be able to be used concurrently as there's a mutex around the sequence number but it's better than errors
+        // a runner with concurrent writes signs them with sequence numbers its SequencedTarget reserves
         let mut wallets: HashMap<Vec<u8>, Wallet> = HashMap::new();
         for i in 0..config.test.num_accounts {
             // create random account
@@ -156,7 +443,7 @@ impl TestRunner {
             } else {
                 // Setup local wallet using private key from arg
                 let mut wallet = Wallet::new_secp256k1(
-                    key.sk,
+                    key.sk.clone(),
                     AccountKind::Ethereum,
                     network_cfg.subnet_id.clone(),
                 )
@@ -177,6 +464,7 @@ impl TestRunner {
                 }
             };
             info!(eth_address=?key.eth_addr, "using wallet for eth address");
+            let thread_id = format!("{i}-{}", key.eth_addr);
 
             if let Some(credits) = config.test.buy_credit {
                 let addr = wallet.address();
@@ -192,21 +480,114 @@ impl TestRunner {
                 info!(eth_addr=?key.eth_addr, f_addr=?addr, "bought credits {credits} in tx {}", tx.hash());
             }
 
-            let target = match config.test.target {
-                ConfigTarget::Sdk => Arc::new(SdkTarget {
-                    provider: provider.clone(),
-                    wallet: wallet.clone(),
-                }),
-                ConfigTarget::S3 => unimplemented!(),
-            };
+            let warmup = Arc::new(Warmup::new(config.test.warmup));
+            let target = account_target(
+                config.test.target,
+                &provider,
+                &wallet,
+                &key.sk,
+                network,
+                &config.test.s3,
+                Some((collector.clone(), thread_id.clone())),
+            )?;
+            let target = SequencedTarget::wrap(target, config.test.concurrent_writes());
+            let target = ChaosTarget::wrap(target, &config.test.chaos);
+            let target = RecordingTarget::wrap(target, &recorder, thread_id.clone());
 
+            let resolver = config.test.resolve().map(|resolve| {
+                Resolver::new(
+                    target.clone(),
+                    collector.clone(),
+                    thread_id.clone(),
+                    resolve,
+                )
+            });
+            let tracker = config.test.inclusion().map(|inclusion| {
+                InclusionTracker::new(
+                    target.clone(),
+                    collector.clone(),
+                    thread_id.clone(),
+                    inclusion,
+                )
+            });
             results.push(TestRunner {
-                provider: provider.clone(),
                 collector: collector.clone(),
                 target,
                 wallet,
                 test: config.test.clone(),
-                thread_id: format!("{i}-{}", key.eth_addr),
+                thread_id,
+                warmup,
+                resolver,
+                tracker,
+                digests: Default::default(),
+                stop: Default::default(),
+            })
+        }
+
+        Ok(results)
+    }
+
+    /// Sets up runners that share one [InMemoryTarget] and skips everything that needs the
+    /// network (funding, sequence init and credits)
+    fn prepare_in_memory(
+        config: TestConfig,
+        collector: Arc<Collector>,
+        recorder: Option<Arc<TraceRecorder>>,
+    ) -> Result<Vec<Self>> {
+        let target = Arc::new(InMemoryTarget::new(config.test.memory.clone()));
+        let results = Self::prepare_shared(config, target, collector, recorder)?;
+        info!("prepared {} in-memory accounts", results.len());
+        Ok(results)
+    }
+
+    /// Sets up runners with accounts that only exist locally, which all run against `target`
+    fn prepare_shared(
+        config: TestConfig,
+        target: Arc<dyn Target>,
+        collector: Arc<Collector>,
+        recorder: Option<Arc<TraceRecorder>>,
+    ) -> Result<Vec<Self>> {
+        let network_cfg = config.network.get_config();
+        let target = SequencedTarget::wrap(target, config.test.concurrent_writes());
+        let target = ChaosTarget::wrap(target, &config.test.chaos);
+
+        let mut results = Vec::with_capacity(config.test.num_accounts as usize);
+        for i in 0..config.test.num_accounts {
+            let sk = random_secretkey();
+            let eth_addr = EthAddress::from(sk.public_key());
+            let wallet =
+                Wallet::new_secp256k1(sk, AccountKind::Ethereum, network_cfg.subnet_id.clone())
+                    .context("failed to create wallet")?;
+            let thread_id = format!("{i}-{eth_addr}");
+
+            let target = RecordingTarget::wrap(target.clone(), &recorder, thread_id.clone());
+            let resolver = config.test.resolve().map(|resolve| {
+                Resolver::new(
+                    target.clone(),
+                    collector.clone(),
+                    thread_id.clone(),
+                    resolve,
+                )
+            });
+            let tracker = config.test.inclusion().map(|inclusion| {
+                InclusionTracker::new(
+                    target.clone(),
+                    collector.clone(),
+                    thread_id.clone(),
+                    inclusion,
+                )
+            });
+            results.push(TestRunner {
+                collector: collector.clone(),
+                target,
+                wallet,
+                test: config.test.clone(),
+                thread_id,
+                warmup: Arc::new(Warmup::new(config.test.warmup)),
+                resolver,
+                tracker,
+                digests: Default::default(),
+                stop: Default::default(),
             })
         }
 
@@ -220,37 +601,57 @@ impl TestRunner {
         size: i64,
         broadcast_mode: Broadcast,
         overwrite: bool,
+        stage: &str,
     ) -> Result<()> {
-        let (temp_file, size) = temp_file(size).await?;
+        // hashed up front from the seed, so hashing isn't part of the upload time
+        let seed = rand::random();
+        let digest = RandomReader::digest(seed, size as u64);
+        let payload = Payload::new(RandomReader::with_seed(seed, size as u64), size as u64);
         let mut metadata = HashMap::new();
-        metadata.insert("upload bench test".to_string(), key.to_string());
+        metadata.insert("upload-bench-test".to_string(), key.to_string());
 
         let start = Utc::now();
+        let started = Instant::now();
         let mut operation = Operation {
             id: self.thread_id.clone(),
             start,
             op_type: OperationType::Put,
             file: key.to_string(),
             size,
+            stage: stage.to_string(),
+            warmup: self.warmup.begin(),
             ..Default::default()
         };
 
         return match self
             .target
-            .add_object(
-                bucket,
-                key,
-                temp_file.file_path(),
-                metadata,
-                overwrite,
-                broadcast_mode,
-            )
+            .add_object(bucket, key, payload, metadata, overwrite, broadcast_mode)
             .await
         {
-            Ok(_) => {
+            Ok(tx) => {
                 let end = Utc::now();
                 operation.end = end;
+                let warmup = operation.warmup;
                 self.collector.collect(operation).await?;
+                if let (Some(tracker), Some(hash)) = (&self.tracker, tx) {
+                    tracker.watch(Broadcasted {
+                        hash,
+                        key: key.to_string(),
+                        at: start,
+                        started,
+                        warmup,
+                    });
+                }
+                self.digests.lock().unwrap().insert(key.to_string(), digest);
+                if let Some(resolver) = &self.resolver {
+                    resolver.watch(Committed {
+                        bucket: bucket.address(),
+                        key: key.to_string(),
+                        at: end,
+                        warmup,
+                        digest: Some(digest),
+                    });
+                }
 
                 let time = end.signed_duration_since(start).num_milliseconds();
                 let address = bucket.address();
@@ -275,12 +676,80 @@ impl TestRunner {
         };
     }
 
+    /// Lists the first page of blobs under the upload prefix
+    async fn list_blobs(&self, bucket: &Bucket) -> Result<()> {
+        let prefix = self.test.upload.get_key_with_prefix("");
+        self.list_page(bucket, &prefix, None, None, None, "")
+            .await?;
+        Ok(())
+    }
+
+    /// Lists one page of keys as a `List` operation tagged with `stage`. With a `delimiter`
+    /// the page is a directory listing, otherwise it has no common prefixes
+    async fn list_page(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: Option<&str>,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+        stage: &str,
+    ) -> Result<ListPage> {
+        let mut operation = Operation {
+            id: self.thread_id.clone(),
+            op_type: OperationType::List,
+            file: prefix.to_string(),
+            stage: stage.to_string(),
+            warmup: self.warmup.begin(),
+            ..Default::default()
+        };
+
+        operation.start = Utc::now();
+        let result = match delimiter {
+            Some(delimiter) => {
+                self.target
+                    .list_directory(bucket, prefix, delimiter, start_key, limit)
+                    .await
+            }
+            None => self
+                .target
+                .list_objects(bucket, prefix, start_key, limit)
+                .await
+                .map(|(keys, next_key)| ListPage {
+                    keys,
+                    next_key,
+                    ..Default::default()
+                }),
+        };
+        match result {
+            Ok(page) => {
+                operation.end = Utc::now();
+                self.collector.collect(operation).await?;
+                debug!(
+                    prefix,
+                    keys = page.keys.len(),
+                    common_prefixes = page.common_prefixes.len(),
+                    "listed"
+                );
+                Ok(page)
+            }
+            Err(e) => {
+                operation.end = Utc::now();
+                operation.error = e.to_string();
+                self.collector.collect(operation).await?;
+                error!(error=?e, %prefix, "failed to list");
+                Err(e)
+            }
+        }
+    }
+
     async fn delete_blob(&self, key: &str, bucket: &Bucket) -> Result<()> {
         let mut operation = Operation {
             id: self.thread_id.clone(),
             op_type: OperationType::Delete,
             file: key.to_string(),
             error: "".to_string(),
+            warmup: self.warmup.begin(),
             ..Default::default()
         };
 
@@ -291,6 +760,7 @@ impl TestRunner {
                 let end = Utc::now();
                 operation.end = end;
                 self.collector.collect(operation).await?;
+                self.digests.lock().unwrap().remove(key);
 
                 let time = end.signed_duration_since(start);
                 debug!(key, time=?time, "deleted");
@@ -308,6 +778,9 @@ impl TestRunner {
     }
 }
 
+/// Waits until some of the uploaded `keys` can be downloaded, so the downloads don't fail
+/// on blobs the network hasn't resolved yet. Only needed when uploads aren't polled by a
+/// [Resolver]
 async fn loop_until_blob_found(
     keys: &[String],
     target: Arc<dyn Target>,
@@ -331,15 +804,17 @@ async fn loop_until_blob_found(
     for (i, key) in keys.iter().enumerate() {
         while retries > 0 {
             let writer = tokio::io::sink();
-            if let Ok(time) = target
+            if target
                 .get_object(machine, key, Box::new(writer), None)
                 .await
+                .is_ok()
             {
-                let time_to_finish = start.elapsed();
                 info!(
-                    request_duration=?time,
                     "able to download/resolve object {}/{} in bucket {} (took {:?}).",
-                    i+1, keys.len(), machine.address(), time_to_finish
+                    i + 1,
+                    keys.len(),
+                    machine.address(),
+                    start.elapsed()
                 );
                 break;
             }
@@ -353,16 +828,430 @@ async fn loop_until_blob_found(
     }
 }
 
-async fn temp_file(size: i64) -> Result<(async_tempfile::TempFile, i64)> {
-    let mut file = async_tempfile::TempFile::new().await?;
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::config::S3Config;
+    use crate::stats::ops::OperationType;
+    use crate::targets::s3::S3Target;
+    use crate::targets::stub_server::{Response, StubServer};
+    use serde_json::json;
 
-    let random_data = {
-        let mut rng = thread_rng();
-        let mut random_data = vec![0; size as usize];
-        rng.fill(&mut random_data[..]);
-        random_data
-    };
-    file.write_all(&random_data).await?;
-    file.flush().await?;
-    Ok((file, size))
+    /// A config for the memory target with `test` as its test section. The upload creates a
+    /// new bucket
+    pub(crate) fn memory_config(mut test: serde_json::Value) -> TestConfig {
+        test["target"] = json!("memory");
+        test["upload"]["bucket"] = serde_json::Value::Null;
+        serde_json::from_value(json!({
+            "funderPrivateKey": "",
+            "network": "devnet",
+            "test": test,
+        }))
+        .unwrap()
+    }
+
+    /// Runners prepared for a test and the collector recording their operations
+    pub(crate) struct TestRun {
+        pub(crate) runners: Vec<TestRunner>,
+        collector: Arc<Collector>,
+    }
+
+    impl TestRun {
+        pub(crate) async fn prepare(config: TestConfig) -> Self {
+            let collector = Arc::new(Collector::new());
+            let runners = TestRunner::prepare(config, collector.clone(), None)
+                .await
+                .unwrap();
+            Self { runners, collector }
+        }
+
+        /// Executes the runners one after another
+        async fn execute(&self) {
+            for runner in &self.runners {
+                runner.execute().await.unwrap();
+            }
+        }
+
+        /// Drops the runners and returns the collector with all their operations
+        pub(crate) async fn finish(self) -> Collector {
+            drop(self.runners);
+            let mut collector = Arc::try_unwrap(self.collector).ok().unwrap();
+            collector.close().await;
+            collector
+        }
+    }
+
+    /// Executes a run of `test` against the memory target
+    async fn run_memory(test: serde_json::Value) -> Collector {
+        let run = TestRun::prepare(memory_config(test)).await;
+        run.execute().await;
+        run.finish().await
+    }
+
+    #[tokio::test]
+    async fn test_execute_against_s3_target() {
+        let server = StubServer::start(|request| {
+            if request.path.starts_with("/recall-loader-") {
+                Response::new(200, "").header("location", "/t01001")
+            } else {
+                Response::new(200, "")
+            }
+        })
+        .await;
+        let config = memory_config(json!({
+            "numAccounts": 1,
+            "upload": { "blobCount": 3, "prefix": "foo", "blobSize": 64 },
+            "delete": true
+        }));
+        let target = Arc::new(
+            S3Target::new(&S3Config {
+                endpoint: Some(server.url()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let collector = Arc::new(Collector::new());
+        let runners = TestRunner::prepare_shared(config, target, collector.clone(), None).unwrap();
+        let run = TestRun { runners, collector };
+        run.execute().await;
+
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        assert_eq!(6, ops.len());
+        assert!(ops.iter().all(|op| op.error.is_empty()));
+
+        let puts = server
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "PUT" && request.path.contains("/foo"))
+            .collect::<Vec<_>>();
+        assert_eq!(3, puts.len());
+        for put in puts {
+            let key = put.path.splitn(3, '/').nth(2).unwrap().to_string();
+            assert_eq!(
+                Some(key.as_str()),
+                put.header("x-amz-meta-upload-bench-test")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_against_memory_target() {
+        let collector = run_memory(json!({
+            "numAccounts": 2,
+            "upload": { "blobCount": 5, "prefix": "foo", "blobSize": 64 },
+            "download": { "concurrency": 2 },
+            "resolve": {},
+            "delete": true
+        }))
+        .await;
+        let ops = collector.get_operations();
+        for op_type in [
+            OperationType::Put,
+            OperationType::Resolve,
+            OperationType::Get,
+            OperationType::Delete,
+        ] {
+            let ops: Vec<_> = ops.iter().filter(|op| op.op_type == op_type).collect();
+            assert_eq!(10, ops.len(), "{op_type}");
+            assert!(ops.iter().all(|op| op.error.is_empty()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_reports_corrupted_downloads() {
+        let collector = run_memory(json!({
+            "numAccounts": 1,
+            "upload": { "blobCount": 3, "prefix": "foo", "blobSize": 64 },
+            "download": { "concurrency": 2 },
+            "delete": true,
+            "memory": { "corruptionRate": 1.0 }
+        }))
+        .await;
+        let ops = collector.get_operations();
+        let gets: Vec<_> = ops
+            .iter()
+            .filter(|op| op.op_type == OperationType::Get)
+            .collect();
+        assert_eq!(3, gets.len());
+        for get in gets {
+            assert!(get.corrupted, "{}", get.file);
+            let expected = format!("integrity check failed for {}", get.file);
+            assert!(get.error.starts_with(&expected), "{}", get.error);
+        }
+        let summaries = collector.summaries();
+        let get = summaries.iter().find(|s| s.operation == "Get").unwrap();
+        assert_eq!((3, 3, 3), (get.total, get.errors, get.integrity_errors));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_downloads_wait_for_resolution() {
+        let started = Instant::now();
+        let collector = run_memory(json!({
+            "numAccounts": 1,
+            "upload": { "blobCount": 3, "prefix": "foo", "blobSize": 64 },
+            "download": { "concurrency": 2 },
+            "delete": true,
+            "memory": { "resolveDelayMs": 5000 }
+        }))
+        .await;
+        // polled every two seconds until the uploads resolved, without recording the polls
+        assert_eq!(Duration::from_secs(6), started.elapsed());
+        let gets = collector
+            .get_operations()
+            .into_iter()
+            .filter(|op| op.op_type == OperationType::Get)
+            .collect::<Vec<_>>();
+        assert_eq!(3, gets.len());
+        assert!(gets.iter().all(|op| op.error.is_empty()), "{gets:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_execute_for_duration() {
+        let started = Instant::now();
+        let collector = run_memory(json!({
+            "numAccounts": 1,
+            "upload": { "durationSecs": 1, "prefix": "foo", "blobSize": 64 },
+            "delete": true,
+            "memory": { "put": { "latencyMs": 100 } }
+        }))
+        .await;
+        // the tenth upload finishes right when the duration is up
+        assert_eq!(Duration::from_secs(1), started.elapsed());
+        let ops = collector.get_operations();
+        for op_type in [OperationType::Put, OperationType::Delete] {
+            assert_eq!(10, ops.iter().filter(|op| op.op_type == op_type).count());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_deletes_uploaded_blobs() {
+        let mut run = TestRun::prepare(memory_config(json!({
+            "numAccounts": 1,
+            "upload": { "blobCount": 100, "prefix": "foo", "blobSize": 64 },
+            "download": { "concurrency": 2 },
+            "resolve": {},
+            "delete": true,
+            "memory": { "put": { "latencyMs": 50 }, "resolveDelayMs": 60000 }
+        })))
+        .await;
+        let stop = CancellationToken::new();
+        run.runners = run
+            .runners
+            .into_iter()
+            .map(|runner| runner.with_stop(stop.clone()))
+            .collect();
+        let started = Instant::now();
+        // stops while the sixth upload is in flight
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(275)).await;
+            stop.cancel();
+        });
+        run.execute().await;
+        // lets the upload in flight finish, but neither waits for the uploads to resolve nor
+        // downloads them
+        assert_eq!(Duration::from_millis(300), started.elapsed());
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        let count = |op_type| ops.iter().filter(|op| op.op_type == op_type).count();
+        assert_eq!(6, count(OperationType::Put));
+        assert_eq!(6, count(OperationType::Delete));
+        assert_eq!(0, count(OperationType::Get));
+        assert_eq!(0, count(OperationType::Resolve));
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_warmup() {
+        let collector = run_memory(json!({
+            "numAccounts": 2,
+            "upload": { "blobCount": 5, "prefix": "foo", "blobSize": 64 },
+            "delete": true,
+            "warmup": { "operations": 3 }
+        }))
+        .await;
+        // every account warms up on its own first three uploads
+        let ops = collector.get_operations();
+        let warmup = ops.iter().filter(|op| op.warmup).collect::<Vec<_>>();
+        assert_eq!(6, warmup.len());
+        assert!(warmup.iter().all(|op| op.op_type == OperationType::Put));
+        let totals = collector
+            .summaries()
+            .into_iter()
+            .map(|summary| (summary.operation, summary.total))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("Delete".to_string(), 10), ("Put".to_string(), 4)],
+            totals
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_execute_pipelined() {
+        let started = Instant::now();
+        let collector = run_memory(json!({
+            "numAccounts": 1,
+            "upload": {
+                "blobCount": 10,
+                "pipelineDepth": 5,
+                "prefix": "foo",
+                "blobSize": 64
+            },
+            "delete": true,
+            "memory": { "put": { "latencyMs": 100 } }
+        }))
+        .await;
+        // two rounds of five uploads in flight, instead of ten uploads one after another
+        assert_eq!(Duration::from_millis(200), started.elapsed());
+        let ops = collector.get_operations();
+        for op_type in [OperationType::Put, OperationType::Delete] {
+            assert_eq!(10, ops.iter().filter(|op| op.op_type == op_type).count());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_query() {
+        let run = TestRun::prepare(memory_config(json!({
+            "numAccounts": 1,
+            "upload": {
+                "blobCount": 0,
+                "prefix": "foo",
+                "blobSize": 64
+            },
+            "delete": true,
+            "query": { "keys": 25, "pageSizes": [10, 25], "passes": 2 }
+        })))
+        .await;
+        let runners = &run.runners;
+        let bucket = runners[0].bucket().await.unwrap();
+        // already in the bucket, so they aren't uploaded again
+        for i in 0..5 {
+            let key = runners[0].key(i);
+            assert!(runners[0].upload(&bucket, &key, "").await.unwrap());
+        }
+        let query = runners[0].test.query.clone().unwrap();
+        runners[0].execute_query(&bucket, &query).await.unwrap();
+        let (left, _) = runners[0]
+            .target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap();
+        assert_eq!(5, left.len());
+
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        let count = |op_type: OperationType, stage: &str| {
+            ops.iter()
+                .filter(|op| op.op_type == op_type && op.stage == stage)
+                .count()
+        };
+        assert_eq!(25, count(OperationType::Put, ""));
+        assert_eq!(6, count(OperationType::List, "10 per page"));
+        assert_eq!(2, count(OperationType::List, "25 per page"));
+        assert!(ops.iter().all(|op| op.error.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_execute_query_by_directory() {
+        let run = TestRun::prepare(memory_config(json!({
+            "numAccounts": 1,
+            "upload": {
+                "blobCount": 0,
+                "prefix": "foo",
+                "blobSize": 64,
+                "layout": { "depth": 2, "fanOut": 3 }
+            },
+            "delete": false,
+            "query": { "keys": 27, "pageSizes": [2], "delimiter": "/" }
+        })))
+        .await;
+        assert_eq!("foo/d2/d1/5", run.runners[0].key(5));
+        run.execute().await;
+
+        let collector = run.finish().await;
+        let lists = collector
+            .get_operations()
+            .into_iter()
+            .filter(|op| op.op_type == OperationType::List)
+            .collect::<Vec<_>>();
+        // two pages for the prefix, each of its 3 directories and each of their 3 directories
+        assert_eq!(26, lists.len());
+        assert!(lists.iter().all(|op| op.error.is_empty()));
+        assert_eq!(2, lists.iter().filter(|op| op.file == "foo/d0/").count());
+        assert_eq!(2, lists.iter().filter(|op| op.file == "foo/d0/d2/").count());
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_key_distribution() {
+        let run = TestRun::prepare(memory_config(json!({
+            "numAccounts": 1,
+            "upload": {
+                "blobCount": 10,
+                "prefix": "foo",
+                "blobSize": 64
+            },
+            "download": {
+                "concurrency": 4,
+                "distribution": { "zipf": { "exponent": 1.5 } },
+                "reads": 500
+            },
+            "delete": false,
+            "resolve": { "pollIntervalMs": 10 }
+        })))
+        .await;
+        run.execute().await;
+        let first = run.runners[0].key(0);
+        let last = run.runners[0].key(9);
+
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        let gets = ops
+            .iter()
+            .filter(|op| op.op_type == OperationType::Get)
+            .collect::<Vec<_>>();
+        assert_eq!(500, gets.len());
+        assert!(gets.iter().all(|op| op.error.is_empty()));
+        let reads = |key: &str| gets.iter().filter(|op| op.file == key).count();
+        let (first, last) = (reads(&first), reads(&last));
+        assert!(first > 5 * last, "{first} vs {last}");
+    }
+
+    #[tokio::test]
+    async fn test_execute_mixed_workload() {
+        let run = TestRun::prepare(memory_config(json!({
+            "numAccounts": 1,
+            "upload": { "blobCount": 5, "prefix": "foo", "blobSize": 64 },
+            "delete": true,
+            "mixed": {
+                "ratios": { "get": 70, "put": 20, "list": 5, "delete": 5 },
+                "operations": 200
+            }
+        })))
+        .await;
+        let runners = &run.runners;
+        let bucket = runners[0].bucket().await.unwrap();
+        let workload = runners[0].test.mixed.clone().unwrap();
+        runners[0].execute_mixed(&bucket, &workload).await.unwrap();
+        // what was left of the key set got cleaned up
+        let (left, _) = runners[0]
+            .target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap();
+        assert!(left.is_empty(), "{left:?}");
+
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        assert!(ops.iter().all(|op| op.error.is_empty()));
+        let count = |op_type: OperationType| ops.iter().filter(|op| op.op_type == op_type).count();
+        let (gets, puts, lists, deletes) = (
+            count(OperationType::Get),
+            count(OperationType::Put),
+            count(OperationType::List),
+            count(OperationType::Delete),
+        );
+        // the initial uploads come on top of the 200 operations
+        assert_eq!(205, gets + puts + lists + deletes);
+        assert!((110..=170).contains(&gets), "{gets} gets");
+        assert!(puts > 5 && lists > 0 && deletes > 0);
+    }
 }
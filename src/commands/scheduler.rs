@@ -0,0 +1,450 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures::future::join_all;
+use recall_sdk::machine::bucket::Bucket;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::runner::TestRunner;
+use crate::config::{ProfileStage, UploadTest, MIN_RATE};
+use crate::stats::report::ScheduleSummary;
+
+/// How often the scheduler looks at the schedule again while it has nothing to issue
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What the scheduler issues uploads by
+pub enum Schedule<'a> {
+    /// A fixed rate across all accounts until `upload` has no more blobs or time left
+    Rate { upload: &'a UploadTest, rate: f64 },
+    /// Accounts and rate change over time, stage by stage
+    Profile(&'a [ProfileStage]),
+}
+
+/// Load the scheduler should offer at one point in time
+#[derive(Debug, PartialEq)]
+struct Slot {
+    stage: usize,
+    accounts: usize,
+    /// Uploads per second across the active accounts, `None` if every active account
+    /// uploads one blob after another
+    rate: Option<f64>,
+}
+
+impl Schedule<'_> {
+    fn stage_names(&self) -> Vec<String> {
+        match self {
+            Schedule::Rate { .. } => vec![String::new()],
+            Schedule::Profile(stages) => stages.iter().map(|stage| stage.name.clone()).collect(),
+        }
+    }
+
+    /// The load to offer `elapsed` after the start, or `None` once the schedule is over
+    fn slot(&self, accounts: usize, issued: u64, elapsed: Duration) -> Option<Slot> {
+        match self {
+            Schedule::Rate { upload, rate } => {
+                let issued_per_account = (issued / accounts as u64) as u32;
+                upload
+                    .has_more(issued_per_account, elapsed)
+                    .then_some(Slot {
+                        stage: 0,
+                        accounts,
+                        rate: Some(*rate),
+                    })
+            }
+            Schedule::Profile(stages) => profile_slot(stages, accounts, elapsed),
+        }
+    }
+}
+
+/// Interpolates between the values of the previous stage and the current one. The profile
+/// starts from no active accounts and no rate
+fn profile_slot(stages: &[ProfileStage], accounts: usize, elapsed: Duration) -> Option<Slot> {
+    let mut stage_start = Duration::ZERO;
+    let (mut prev_accounts, mut prev_rate) = (0.0, 0.0);
+    for (i, stage) in stages.iter().enumerate() {
+        let duration = Duration::from_secs(stage.duration_secs);
+        let stage_accounts = stage
+            .accounts
+            .map_or(accounts, |active| (active as usize).min(accounts))
+            as f64;
+        if elapsed < stage_start + duration {
+            let fraction = (elapsed - stage_start).as_secs_f64() / duration.as_secs_f64();
+            let progress = stage.progress(fraction);
+            return Some(Slot {
+                stage: i,
+                accounts: (prev_accounts + (stage_accounts - prev_accounts) * progress).round()
+                    as usize,
+                rate: stage
+                    .rate
+                    .map(|rate| prev_rate + (rate - prev_rate) * progress),
+            });
+        }
+        stage_start += duration;
+        prev_accounts = stage_accounts;
+        prev_rate = stage.rate.unwrap_or(0.0);
+    }
+    None
+}
+
+struct Account {
+    runner: TestRunner,
+    bucket: Bucket,
+    next_key: AtomicU32,
+    keys: Mutex<Vec<String>>,
+    in_flight: AtomicU64,
+}
+
+/// Stats of one stage while the scheduler runs
+#[derive(Default)]
+struct StageStats {
+    summary: ScheduleSummary,
+    started: Option<Instant>,
+    /// Last time the schedule was in the stage
+    scheduled: Option<Instant>,
+    ended: Option<Instant>,
+    /// Uploads the scheduler tried to issue, integrated over the time it had a rate
+    offered: Option<f64>,
+    total_lag: Duration,
+    max_lag: Duration,
+}
+
+impl StageStats {
+    fn finish(mut self, name: Option<String>) -> ScheduleSummary {
+        let since_start = |until: Option<Instant>| match (self.started, until) {
+            (Some(started), Some(until)) => until.duration_since(started).as_secs_f64(),
+            _ => 0.0,
+        };
+        let (scheduled, elapsed) = (since_start(self.scheduled), since_start(self.ended));
+        let summary = &mut self.summary;
+        summary.stage = name;
+        if elapsed > 0.0 {
+            summary.achieved_rate = summary.completed as f64 / elapsed;
+        }
+        if scheduled > 0.0 {
+            summary.target_rate = self.offered.map(|offered| offered / scheduled);
+        }
+        if summary.issued > 0 {
+            summary.avg_lag_ms = self.total_lag.as_secs_f64() * 1000.0 / summary.issued as f64;
+        }
+        summary.max_lag_ms = self.max_lag.as_secs_f64() * 1000.0;
+        self.summary
+    }
+}
+
+/// Issues uploads across all runners as the `schedule` says, whether or not earlier uploads
+/// have finished, then runs the download and delete phases of every runner on what it
+/// uploaded. Stops issuing uploads once `stop` is cancelled. Returns whether each runner
+/// succeeded and how well the schedule was kept
+pub async fn run_scheduled(
+    runners: Vec<TestRunner>,
+    schedule: Schedule<'_>,
+    stop: &CancellationToken,
+) -> Result<(Vec<bool>, Vec<ScheduleSummary>)> {
+    let mut results = Vec::with_capacity(runners.len());
+    let mut accounts = Vec::with_capacity(runners.len());
+    let buckets = join_all(runners.iter().map(|runner| runner.bucket())).await;
+    for (runner, bucket) in runners.into_iter().zip(buckets) {
+        match bucket {
+            Ok(bucket) => accounts.push(Arc::new(Account {
+                runner,
+                bucket,
+                next_key: AtomicU32::new(0),
+                keys: Mutex::new(Vec::new()),
+                in_flight: AtomicU64::new(0),
+            })),
+            Err(err) => {
+                error!(error=?err, "failed to setup bucket");
+                results.push(false);
+            }
+        }
+    }
+    if accounts.is_empty() {
+        bail!("no runner could setup a bucket");
+    }
+
+    let names = schedule.stage_names();
+    let mut stages = names
+        .iter()
+        .map(|_| StageStats::default())
+        .collect::<Vec<_>>();
+    let in_flight = Arc::new(AtomicU64::new(0));
+    let mut issued = 0;
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+    let mut last_tick = started;
+    // when the last open loop upload was due, to catch up on missed ones
+    let mut last_due: Option<Instant> = None;
+    while let Some(slot) = schedule
+        .slot(accounts.len(), issued, started.elapsed())
+        .filter(|_| !stop.is_cancelled())
+    {
+        while let Some(result) = tasks.try_join_next() {
+            let (stage, uploaded) = result.unwrap_or((slot.stage, false));
+            stages[stage].summary.count(uploaded);
+        }
+        let now = Instant::now();
+        let stage = &mut stages[slot.stage];
+        stage.started.get_or_insert(now);
+        stage.scheduled = Some(now);
+        if let Some(rate) = slot.rate {
+            *stage.offered.get_or_insert(0.0) += rate * (now - last_tick).as_secs_f64();
+        }
+        last_tick = now;
+
+        let active = &accounts[..slot.accounts];
+        let mut issue = |account: &Arc<Account>, stage: &mut StageStats| {
+            let depth = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            stage.summary.max_queue_depth = stage.summary.max_queue_depth.max(depth);
+            stage.summary.issued += 1;
+            account.in_flight.fetch_add(1, Ordering::SeqCst);
+            let account = account.clone();
+            let in_flight = in_flight.clone();
+            let (index, name) = (slot.stage, names[slot.stage].clone());
+            tasks.spawn(async move {
+                let i = account.next_key.fetch_add(1, Ordering::SeqCst);
+                let key = account.runner.key(i);
+                let uploaded = matches!(
+                    account.runner.upload(&account.bucket, &key, &name).await,
+                    Ok(true)
+                );
+                if uploaded {
+                    account.keys.lock().unwrap().push(key);
+                }
+                account.in_flight.fetch_sub(1, Ordering::SeqCst);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                (index, uploaded)
+            });
+        };
+        match slot.rate {
+            _ if active.is_empty() => {
+                last_due = None;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            // ramps pass through rates too low to wait for
+            Some(rate) if rate >= MIN_RATE => {
+                let interval = Duration::from_secs_f64(1.0 / rate);
+                let due = last_due.map_or(now, |due| due + interval);
+                if now < due {
+                    // the rate can change in the meantime, so don't sleep for long
+                    tokio::time::sleep_until(due.min(now + POLL_INTERVAL)).await;
+                    continue;
+                }
+                let lag = now - due;
+                stage.total_lag += lag;
+                stage.max_lag = stage.max_lag.max(lag);
+                last_due = Some(due);
+                issue(&active[(issued % active.len() as u64) as usize], stage);
+                issued += 1;
+            }
+            Some(_) => {
+                last_due = None;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            None => {
+                last_due = None;
+                for account in active {
+                    if account.in_flight.load(Ordering::SeqCst) == 0 {
+                        issue(account, stage);
+                        issued += 1;
+                    }
+                }
+                tokio::select! {
+                    Some(result) = tasks.join_next() => {
+                        let (stage, uploaded) = result.unwrap_or((slot.stage, false));
+                        stages[stage].summary.count(uploaded);
+                    }
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        }
+    }
+    let schedule_ended = Instant::now();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((stage, uploaded)) = result {
+            stages[stage].summary.count(uploaded);
+        }
+    }
+    // a stage ends when the next one starts, the last one once its uploads are done
+    let mut ended = Instant::now();
+    for stage in stages.iter_mut().rev() {
+        if let Some(started) = stage.started {
+            stage.ended = Some(ended);
+            ended = started;
+        }
+    }
+    info!(
+        issued,
+        "scheduled uploads finished in {:?}",
+        schedule_ended.duration_since(started)
+    );
+    let summaries = stages
+        .into_iter()
+        .zip(names)
+        .filter(|(stage, _)| stage.started.is_some())
+        .map(|(stage, name)| {
+            let name = matches!(schedule, Schedule::Profile(_)).then_some(name);
+            stage.finish(name)
+        })
+        .collect::<Vec<_>>();
+
+    let finished = join_all(accounts.iter().map(|account| async move {
+        let keys = account.keys.lock().unwrap().clone();
+        match account.runner.finish(&account.bucket, &keys).await {
+            Ok(_) => true,
+            Err(e) => {
+                error!(error=?e, "Failed to run test");
+                false
+            }
+        }
+    }))
+    .await;
+    results.extend(finished);
+
+    Ok((results, summaries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::runner::tests::{memory_config, TestRun};
+    use crate::stats::ops::OperationType;
+    use serde_json::json;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_loop_keeps_rate() {
+        let config = memory_config(json!({
+            "numAccounts": 2,
+            "upload": { "blobCount": 5, "prefix": "foo", "blobSize": 64, "rate": 50 },
+            "delete": true,
+            "memory": { "put": { "latencyMs": 100 } }
+        }));
+        let upload = config.test.upload.clone();
+        let mut run = TestRun::prepare(config).await;
+
+        let schedule = Schedule::Rate {
+            upload: &upload,
+            rate: 50.0,
+        };
+        let runners = std::mem::take(&mut run.runners);
+        let (results, summaries) = run_scheduled(runners, schedule, &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(vec![true, true], results);
+        let [summary] = summaries.as_slice() else {
+            panic!("expected one summary, got {summaries:?}");
+        };
+        assert_eq!(None, summary.stage);
+        assert_eq!(
+            (10, 10, 0),
+            (summary.issued, summary.completed, summary.failed)
+        );
+        // uploads take 100ms and are issued every 20ms, so they have to overlap
+        assert!(summary.max_queue_depth >= 5, "{summary:?}");
+        assert_eq!(Some(50.0), summary.target_rate);
+        assert_eq!(0.0, summary.max_lag_ms);
+
+        let collector = run.finish().await;
+        let ops = collector.get_operations();
+        for op_type in [OperationType::Put, OperationType::Delete] {
+            assert_eq!(10, ops.iter().filter(|op| op.op_type == op_type).count());
+        }
+    }
+
+    #[test]
+    fn test_profile_slot() {
+        let stages: Vec<ProfileStage> = serde_json::from_str(
+            r#"[
+                { "name": "rampUp", "durationSecs": 10, "shape": "ramp", "accounts": 4, "rate": 100 },
+                { "name": "steps", "durationSecs": 10, "shape": "step", "steps": 2, "rate": 200 },
+                { "name": "hold", "durationSecs": 10 }
+            ]"#,
+        )
+        .unwrap();
+        let slot = |secs: f64| profile_slot(&stages, 8, Duration::from_secs_f64(secs));
+
+        let expected = Slot {
+            stage: 0,
+            accounts: 2,
+            rate: Some(50.0),
+        };
+        assert_eq!(Some(expected), slot(5.0));
+        // no accounts limit means all accounts, reached in two steps
+        let expected = Slot {
+            stage: 1,
+            accounts: 6,
+            rate: Some(150.0),
+        };
+        assert_eq!(Some(expected), slot(14.0));
+        assert_eq!(Some(8), slot(15.0).map(|slot| slot.accounts));
+        // without a rate every active account uploads one blob after another
+        let expected = Slot {
+            stage: 2,
+            accounts: 8,
+            rate: None,
+        };
+        assert_eq!(Some(expected), slot(20.0));
+        assert_eq!(None, slot(30.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_profile_stages() {
+        let config = memory_config(json!({
+            "numAccounts": 2,
+            "upload": { "prefix": "foo", "blobSize": 64 },
+            "delete": true,
+            "memory": { "put": { "latencyMs": 100 } },
+            "profile": [
+                { "name": "steady", "durationSecs": 1, "accounts": 1 },
+                { "name": "spike", "durationSecs": 1, "rate": 40 }
+            ]
+        }));
+        let profile = config.test.profile.clone();
+        let mut run = TestRun::prepare(config).await;
+
+        let (results, summaries) = run_scheduled(
+            std::mem::take(&mut run.runners),
+            Schedule::Profile(&profile),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(vec![true, true], results);
+        let [steady, spike] = summaries.as_slice() else {
+            panic!("expected two summaries, got {summaries:?}");
+        };
+        assert_eq!(Some("steady"), steady.stage.as_deref());
+        assert_eq!(None, steady.target_rate);
+        // a single account uploading one blob after another never overlaps
+        assert_eq!(1, steady.max_queue_depth);
+        assert_eq!(10, steady.issued, "{steady:?}");
+        assert_eq!(Some("spike"), spike.stage.as_deref());
+        assert_eq!(40, spike.issued, "{spike:?}");
+        assert!(spike.max_queue_depth >= 3, "{spike:?}");
+
+        let collector = run.finish().await;
+        let puts = collector
+            .get_operations()
+            .into_iter()
+            .filter(|op| op.op_type == OperationType::Put)
+            .collect::<Vec<_>>();
+        for (summary, name) in [(steady, "steady"), (spike, "spike")] {
+            let tagged = puts.iter().filter(|op| op.stage == name).count() as u64;
+            assert_eq!(summary.issued, tagged);
+        }
+        let stages = collector
+            .summaries()
+            .into_iter()
+            .filter(|summary| summary.operation == "Put")
+            .map(|summary| summary.stage)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![None, Some("steady".to_string()), Some("spike".to_string())],
+            stages
+        );
+    }
+}
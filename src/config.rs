@@ -5,6 +5,11 @@ use rand::prelude::SliceRandom;
 use rand::thread_rng;
 use std::str::FromStr;
 
+use crate::stats::ops::OperationType;
+use anyhow::{bail, Context as _};
+use rand::distributions::{Distribution as _, WeightedIndex};
+use rand::Rng;
+
 #[derive(Debug, Clone, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestConfig {
@@ -13,7 +18,77 @@ pub struct TestConfig {
     pub test: TestRunConfig,
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+/// Lowest upload rate per second a config can ask for
+pub const MIN_RATE: f64 = 0.001;
+
+impl TestConfig {
+    /// Parses a test config file and checks what its schema alone can't
+    pub fn from_slice(config: &[u8]) -> anyhow::Result<Self> {
+        let config: Self = serde_json::from_slice(config)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects configs the schema allows but a test run can't follow, e.g. parts that can't
+    /// be combined
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let upload = &self.test.upload;
+        let profile = &self.test.profile;
+        if profile.is_empty() && upload.blob_count == 0 && upload.duration_secs.is_none() {
+            bail!("upload needs a blobCount or durationSecs unless a profile is set");
+        }
+        if let Some(rate) = upload.rate.filter(|rate| !valid_rate(*rate)) {
+            bail!("the upload rate must be at least {MIN_RATE} per second, got {rate}");
+        }
+        for stage in profile {
+            if stage.duration_secs == 0 {
+                bail!("stage {} needs a durationSecs of at least 1", stage.name);
+            }
+            // a ramp down ends on no uploads at all
+            if let Some(rate) = stage.rate.filter(|rate| *rate != 0.0 && !valid_rate(*rate)) {
+                bail!(
+                    "the rate of stage {} must be 0 or at least {MIN_RATE} per second, got {rate}",
+                    stage.name
+                );
+            }
+        }
+        if self.test.mixed.is_some() && (upload.rate.is_some() || !profile.is_empty()) {
+            bail!("a mixed workload can't be combined with an upload rate or a load profile");
+        }
+        if self.test.query.is_some()
+            && (self.test.mixed.is_some() || upload.rate.is_some() || !profile.is_empty())
+        {
+            bail!(
+                "a listing benchmark can't be combined with a mixed workload, an upload rate or a \
+                 load profile"
+            );
+        }
+        if let Some(breaker) = &self.test.circuit_breaker {
+            if breaker.max_error_rate.is_none() && breaker.max_consecutive_errors.is_none() {
+                bail!("the circuit breaker needs a maxErrorRate or maxConsecutiveErrors");
+            }
+            if breaker.max_consecutive_errors == Some(0) {
+                bail!("the circuit breaker's maxConsecutiveErrors must be at least 1");
+            }
+            if let Some(rate) = breaker.max_error_rate {
+                if !(0.0..=1.0).contains(&rate) {
+                    bail!("the circuit breaker's maxErrorRate must be between 0.0 and 1.0");
+                }
+            }
+            if breaker.window == 0 {
+                bail!("the circuit breaker's window must be at least 1");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lower rates would space the uploads further apart than any run lasts
+fn valid_rate(rate: f64) -> bool {
+    rate.is_finite() && rate >= MIN_RATE
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestRunConfig {
     pub num_accounts: i32,
@@ -25,6 +100,242 @@ pub struct TestRunConfig {
     /// Only public for cli to set, should use getter
     pub download: Option<DownloadTest>,
     pub delete: bool,
+    /// Connection settings, only used with the s3 target
+    #[serde(default)]
+    pub s3: S3Config,
+    /// Simulated latency and failures, only used with the memory target
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    /// Faults to inject on top of any target
+    #[serde(default)]
+    pub chaos: Option<ChaosConfig>,
+    /// Pass/fail criteria checked against the results at the end of the run
+    #[serde(default)]
+    pub assertions: AssertionsConfig,
+    /// Stages that shape the upload load over time. Replaces `upload.blobCount`,
+    /// `upload.durationSecs` and `upload.rate` when set
+    #[serde(default)]
+    pub profile: Vec<ProfileStage>,
+    /// Mixed traffic over a live key set, run by every account instead of the upload,
+    /// download and delete phases
+    #[serde(default)]
+    pub mixed: Option<MixedWorkload>,
+    /// Operations at the start of every account that run but are left out of the results
+    #[serde(default)]
+    pub warmup: Option<WarmupConfig>,
+    /// How uploaded blobs are polled until they can be downloaded. They are only polled if
+    /// it is set, otherwise downloads only wait for a few of them
+    #[serde(default)]
+    pub resolve: Option<ResolveConfig>,
+    /// Listing benchmark, run by every account instead of the upload, download and delete
+    /// phases
+    #[serde(default)]
+    pub query: Option<QueryTest>,
+    /// How the transactions of uploads are polled until they are included in a block.
+    /// Defaults apply if not set and uploads use the async or sync broadcast mode
+    #[serde(default)]
+    pub inclusion: Option<InclusionConfig>,
+    /// Stops every runner early once an operation type keeps failing
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// Measures the time from broadcasting every upload until its transaction is in a block
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionConfig {
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Gives up on a transaction this long after its broadcast
+    #[serde(default = "default_inclusion_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Transactions of one account that are polled at the same time
+    #[serde(default = "default_resolve_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_inclusion_timeout_ms() -> u64 {
+    60_000
+}
+
+impl Default for InclusionConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: default_poll_interval_ms(),
+            timeout_ms: default_inclusion_timeout_ms(),
+            concurrency: default_resolve_concurrency(),
+        }
+    }
+}
+
+/// Measures the time from the commit of every upload to its first successful download
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveConfig {
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Gives up on a blob this long after its commit
+    #[serde(default = "default_resolve_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Polls of one account in flight at the same time
+    #[serde(default = "default_resolve_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_resolve_timeout_ms() -> u64 {
+    60_000
+}
+
+fn default_resolve_concurrency() -> usize {
+    16
+}
+
+impl Default for ResolveConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: default_poll_interval_ms(),
+            timeout_ms: default_resolve_timeout_ms(),
+            concurrency: default_resolve_concurrency(),
+        }
+    }
+}
+
+/// How long every account warms up, e.g. `{ "operations": 20 }` or `{ "durationSecs": 5 }`
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WarmupConfig {
+    /// The first operations of the account
+    Operations(u32),
+    /// The operations that start within this many seconds of the account's first one
+    DurationSecs(u64),
+}
+
+impl TestRunConfig {
+    /// How to resolve uploads, if they are resolved at all
+    pub fn resolve(&self) -> Option<ResolveConfig> {
+        self.resolve.clone()
+    }
+
+    /// How to track the inclusion of upload transactions, if they are tracked at all
+    pub fn inclusion(&self) -> Option<InclusionConfig> {
+        match (&self.inclusion, self.upload.broadcast_mode) {
+            (Some(inclusion), _) => Some(inclusion.clone()),
+            (None, Broadcast::Async | Broadcast::Sync) => Some(InclusionConfig::default()),
+            (None, Broadcast::Commit) => None,
+        }
+    }
+
+    /// Whether an account can have more than one write in flight at the same time
+    pub fn concurrent_writes(&self) -> bool {
+        self.upload.pipeline_depth > 1 || self.upload.rate.is_some() || !self.profile.is_empty()
+    }
+}
+
+/// YCSB-like workload: every account uploads `upload.blobCount` blobs as its initial key set,
+/// then picks each operation at random by the configured ratios
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MixedWorkload {
+    pub ratios: OperationRatios,
+    /// How many operations each account runs after the initial uploads
+    #[serde(default)]
+    pub operations: u32,
+    /// Keep running operations until this many seconds have passed instead of running
+    /// `operations` of them
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+impl MixedWorkload {
+    /// Whether to run another operation after `done` of them, `elapsed` after the first one
+    pub fn has_more(&self, done: u32, elapsed: std::time::Duration) -> bool {
+        match self.duration_secs {
+            Some(secs) => elapsed < std::time::Duration::from_secs(secs),
+            None => done < self.operations,
+        }
+    }
+}
+
+/// Relative weights of the operations in a mixed workload, e.g. 70/20/5/5. Gets and
+/// deletes turn into puts while the key set is empty
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationRatios {
+    #[serde(default)]
+    pub get: u32,
+    #[serde(default)]
+    pub put: u32,
+    #[serde(default)]
+    pub list: u32,
+    #[serde(default)]
+    pub delete: u32,
+}
+
+impl OperationRatios {
+    pub fn weights(&self) -> [(OperationType, u32); 4] {
+        [
+            (OperationType::Get, self.get),
+            (OperationType::Put, self.put),
+            (OperationType::List, self.list),
+            (OperationType::Delete, self.delete),
+        ]
+    }
+}
+
+/// One stage of a load profile. Operations issued during the stage are tagged with its name
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileStage {
+    pub name: String,
+    pub duration_secs: u64,
+    /// How the stage gets from the accounts and rate of the previous stage to its own
+    #[serde(default)]
+    pub shape: StageShape,
+    /// Number of steps for the `step` shape
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    /// Accounts that upload at the end of the stage (all accounts if not set)
+    #[serde(default)]
+    pub accounts: Option<u32>,
+    /// Uploads per second across the active accounts at the end of the stage. Without it,
+    /// every active account uploads one blob after another
+    #[serde(default)]
+    pub rate: Option<f64>,
+}
+
+fn default_steps() -> u32 {
+    4
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StageShape {
+    /// Jump to the stage's values right away, e.g. for a hold or a spike
+    #[default]
+    Hold,
+    /// Change linearly over the stage
+    Ramp,
+    /// Change in `steps` equal increments over the stage
+    Step,
+}
+
+impl ProfileStage {
+    /// How far (0.0 - 1.0) the stage has moved from the previous values to its own, after
+    /// `fraction` of its duration
+    pub fn progress(&self, fraction: f64) -> f64 {
+        match self.shape {
+            StageShape::Hold => 1.0,
+            StageShape::Ramp => fraction.clamp(0.0, 1.0),
+            StageShape::Step => {
+                let steps = self.steps.max(1) as f64;
+                (((fraction * steps).floor() + 1.0) / steps).min(1.0)
+            }
+        }
+    }
 }
 
 fn deserialize_address<'de, D>(deserializer: D) -> Result<Option<Address>, D::Error>
@@ -38,14 +349,37 @@ where
         .map_err(serde::de::Error::custom)
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+fn serialize_address<S>(address: &Option<Address>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serde::Serialize::serialize(&address.as_ref().map(Address::to_string), serializer)
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UploadTest {
     /// Creates a new bucket if none
-    #[serde(deserialize_with = "deserialize_address")]
+    #[serde(
+        serialize_with = "serialize_address",
+        deserialize_with = "deserialize_address"
+    )]
     pub bucket: Option<Address>,
     /// How many blobs to upload
+    #[serde(default)]
     pub blob_count: u32,
+    /// Keep uploading until this many seconds have passed instead of uploading `blob_count`
+    /// blobs. The download and delete phases then run on everything that was uploaded
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+    /// Issue this many uploads per second across all accounts, whether or not earlier
+    /// uploads have finished. Without it every account uploads one blob after another
+    #[serde(default)]
+    pub rate: Option<f64>,
+    /// Uploads each account keeps in flight at the same time, with their sequence numbers
+    /// assigned locally
+    #[serde(default = "default_pipeline_depth")]
+    pub pipeline_depth: u32,
     /// Prefix blobs should be stored under (e.g. foo/bar). Should not end in /
     pub prefix: String,
     /// Size of each blob in bytes
@@ -56,15 +390,59 @@ pub struct UploadTest {
     /// Broadcast mode for the transactions in the tests
     #[serde(default)]
     pub broadcast_mode: Broadcast,
+    /// Spreads the blobs over a tree of directories under the prefix instead of putting
+    /// them all right under it
+    #[serde(default)]
+    pub layout: Option<KeyLayout>,
+}
+
+fn default_pipeline_depth() -> u32 {
+    1
+}
+
+/// Directory tree of the uploaded keys, e.g. `foo/d2/d1/5` with a depth of 2 and a fan-out of 3
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyLayout {
+    /// Directory levels between the prefix and the blobs
+    pub depth: u32,
+    /// Subdirectories per directory
+    pub fan_out: u32,
 }
 
 impl UploadTest {
     pub fn get_key_with_prefix(&self, name: &str) -> String {
         format!("{}/{name}", prefix_normalized(&self.prefix))
     }
+
+    /// Key of the `i`-th blob. With a layout, consecutive blobs go to different directories,
+    /// so the tree fills up evenly
+    pub fn key(&self, i: u32) -> String {
+        let Some(layout) = self.layout else {
+            return self.get_key_with_prefix(&i.to_string());
+        };
+        let fan_out = layout.fan_out.max(1);
+        let mut path = Vec::with_capacity(layout.depth as usize + 1);
+        let mut rest = i;
+        for _ in 0..layout.depth {
+            path.push(format!("d{}", rest % fan_out));
+            rest /= fan_out;
+        }
+        path.push(i.to_string());
+        self.get_key_with_prefix(&path.join("/"))
+    }
+
+    /// Whether another blob should be uploaded after `uploaded` attempts, `elapsed` after
+    /// the uploads started
+    pub fn has_more(&self, uploaded: u32, elapsed: std::time::Duration) -> bool {
+        match self.duration_secs {
+            Some(secs) => elapsed < std::time::Duration::from_secs(secs),
+            None => uploaded < self.blob_count,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Broadcast {
     /// Return immediately after the transaction is broadcasted without waiting for check results.
@@ -76,12 +454,199 @@ pub enum Broadcast {
     Commit,
 }
 
-#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Target {
     #[default]
     Sdk,
     S3,
+    /// Reads and lists over plain HTTP against the objects API, writes go through the SDK
+    Http,
+    /// Keeps everything in memory, for dry runs without a network
+    Memory,
+}
+
+/// Settings for the S3-compatible gateway. Shared between the config file and the cli.
+#[derive(Debug, Clone, clap::Args, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Config {
+    /// The S3 gateway endpoint (e.g. http://localhost:8014), required for the s3 target
+    #[arg(long = "s3-endpoint", env = "RECALL_S3_ENDPOINT")]
+    pub endpoint: Option<String>,
+    /// The region used to sign S3 requests
+    #[arg(
+        long = "s3-region",
+        env = "RECALL_S3_REGION",
+        default_value = "us-east-1"
+    )]
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// The S3 access key id
+    #[arg(
+        long = "s3-access-key-id",
+        env = "RECALL_S3_ACCESS_KEY_ID",
+        hide_env_values = true
+    )]
+    pub access_key_id: Option<String>,
+    /// The S3 secret access key
+    #[arg(
+        long = "s3-secret-access-key",
+        env = "RECALL_S3_SECRET_ACCESS_KEY",
+        hide_env_values = true
+    )]
+    #[serde(skip_serializing)]
+    pub secret_access_key: Option<String>,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            region: default_s3_region(),
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryConfig {
+    /// Max number of keys returned per list call
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+    #[serde(default)]
+    pub put: MemoryOp,
+    #[serde(default)]
+    pub get: MemoryOp,
+    #[serde(default)]
+    pub list: MemoryOp,
+    #[serde(default)]
+    pub delete: MemoryOp,
+    /// Time after an upload before the object can be downloaded
+    #[serde(default)]
+    pub resolve_delay_ms: u64,
+    /// Time after an upload with the async or sync broadcast mode before its transaction
+    /// is included in a block
+    #[serde(default)]
+    pub inclusion_delay_ms: u64,
+    /// Chance (0.0 - 1.0) that a download returns altered content
+    #[serde(default)]
+    pub corruption_rate: f64,
+}
+
+fn default_page_size() -> usize {
+    100
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            page_size: default_page_size(),
+            put: Default::default(),
+            get: Default::default(),
+            list: Default::default(),
+            delete: Default::default(),
+            resolve_delay_ms: 0,
+            inclusion_delay_ms: 0,
+            corruption_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryOp {
+    /// Added to every call of this operation
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Chance (0.0 - 1.0) that a call fails
+    #[serde(default)]
+    pub failure_rate: f64,
+}
+
+/// When to stop a run early, checked separately for every operation type
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreakerConfig {
+    /// Highest share (0.0 - 1.0) of the last `window` operations that may fail
+    #[serde(default)]
+    pub max_error_rate: Option<f64>,
+    /// Number of operations the error rate is taken over. The error rate isn't checked
+    /// before that many operations finished
+    #[serde(default = "default_breaker_window")]
+    pub window: usize,
+    /// Most operations that may fail in a row
+    #[serde(default)]
+    pub max_consecutive_errors: Option<u32>,
+}
+
+fn default_breaker_window() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChaosConfig {
+    #[serde(default)]
+    pub put: ChaosOp,
+    #[serde(default)]
+    pub get: ChaosOp,
+    #[serde(default)]
+    pub list: ChaosOp,
+    #[serde(default)]
+    pub delete: ChaosOp,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChaosOp {
+    /// Fixed delay before every call
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Random extra delay of up to this many milliseconds
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Fail calls that take longer than this (0 disables the timeout)
+    #[serde(default)]
+    pub timeout_ms: u64,
+    /// Chance (0.0 - 1.0) that a call fails without reaching the target
+    #[serde(default)]
+    pub error_rate: f64,
+    /// Chance (0.0 - 1.0) that a download is cut off part way, only used for gets
+    #[serde(default)]
+    pub truncate_rate: f64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionsConfig {
+    #[serde(default)]
+    pub put: Option<OperationAssertions>,
+    #[serde(default)]
+    pub get: Option<OperationAssertions>,
+    #[serde(default)]
+    pub list: Option<OperationAssertions>,
+    #[serde(default)]
+    pub delete: Option<OperationAssertions>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationAssertions {
+    /// Highest share (0.0 - 1.0) of the operations that may fail
+    #[serde(default)]
+    pub max_error_rate: Option<f64>,
+    /// Lowest acceptable number of operations per second
+    #[serde(default)]
+    pub min_objects_per_sec: Option<f64>,
+    /// Highest acceptable p99 latency in milliseconds
+    #[serde(default)]
+    pub max_p99_ms: Option<f64>,
 }
 
 impl From<Broadcast> for BroadcastMode {
@@ -98,16 +663,108 @@ fn true_bool() -> bool {
     true
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DownloadTest {
     concurrency: i32,
+    /// How the keys to download are picked from the uploaded ones
+    #[serde(default)]
+    pub distribution: KeyDistribution,
+    /// How many downloads every account runs, one per uploaded key if not set
+    #[serde(default)]
+    pub reads: Option<u32>,
 }
 
 impl DownloadTest {
     pub fn concurrency(&self) -> i32 {
         self.concurrency
     }
+
+    /// Indexes of the keys to download, out of `n` uploaded keys in upload order
+    pub fn pick(&self, n: usize, rng: &mut impl Rng) -> anyhow::Result<Vec<usize>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let reads = self.reads.map_or(n, |reads| reads as usize);
+        let Some(weights) = self.distribution.weights(n) else {
+            return Ok((0..n).cycle().take(reads).collect());
+        };
+        let index = WeightedIndex::new(weights)
+            .context("key distribution needs at least one key with a positive weight")?;
+        Ok((0..reads).map(|_| index.sample(rng)).collect())
+    }
+}
+
+/// Access pattern of the downloads, e.g. `"uniform"` or `{ "zipf": { "exponent": 1.2 } }`
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyDistribution {
+    /// Every key in upload order, starting over once all of them were read
+    #[default]
+    Sequential,
+    /// Every key is equally likely
+    Uniform,
+    /// The `n`-th uploaded key is read in proportion to `1 / n^exponent`, so the first keys
+    /// are the hottest
+    Zipf {
+        #[serde(default = "default_zipf_exponent")]
+        exponent: f64,
+    },
+    /// `hotReads` of the reads go to the first `hotFraction` of the keys, the rest are
+    /// spread over the other keys
+    #[serde(rename_all = "camelCase")]
+    Hotspot {
+        #[serde(default = "default_hot_fraction")]
+        hot_fraction: f64,
+        #[serde(default = "default_hot_reads")]
+        hot_reads: f64,
+    },
+    /// Like `zipf`, but the most recently uploaded keys are the hottest
+    Latest {
+        #[serde(default = "default_zipf_exponent")]
+        exponent: f64,
+    },
+}
+
+fn default_zipf_exponent() -> f64 {
+    1.0
+}
+
+fn default_hot_fraction() -> f64 {
+    0.2
+}
+
+fn default_hot_reads() -> f64 {
+    0.8
+}
+
+impl KeyDistribution {
+    /// Relative read weight of each of `n` keys in upload order, none for sequential reads
+    pub fn weights(&self, n: usize) -> Option<Vec<f64>> {
+        let zipf = |rank: usize, exponent: f64| 1.0 / (rank as f64).powf(exponent);
+        let weights = match *self {
+            KeyDistribution::Sequential => return None,
+            KeyDistribution::Uniform => vec![1.0; n],
+            KeyDistribution::Zipf { exponent } => (0..n).map(|i| zipf(i + 1, exponent)).collect(),
+            KeyDistribution::Latest { exponent } => (0..n).map(|i| zipf(n - i, exponent)).collect(),
+            KeyDistribution::Hotspot {
+                hot_fraction,
+                hot_reads,
+            } => {
+                let hot = ((n as f64 * hot_fraction).ceil() as usize).max(1);
+                if hot >= n {
+                    vec![1.0; n]
+                } else {
+                    let hot_weight = hot_reads.clamp(0.0, 1.0) / hot as f64;
+                    let cold_weight = (1.0 - hot_reads.clamp(0.0, 1.0)) / (n - hot) as f64;
+                    (0..n)
+                        .map(|i| if i < hot { hot_weight } else { cold_weight })
+                        .collect()
+                }
+            }
+        };
+        Some(weights)
+    }
 }
 
 impl UploadTest {
@@ -127,9 +784,39 @@ pub fn prefix_normalized(prefix: &str) -> String {
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+/// Listing benchmark: every account fills its bucket up to `keys` blobs under the upload
+/// prefix, then pages through all of them once per page size and pass
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct QueryTest {}
+pub struct QueryTest {
+    /// Blobs the bucket holds under the upload prefix. Only the missing ones are uploaded,
+    /// so a populated `upload.bucket` can be reused
+    pub keys: u32,
+    /// Keys per page, each one is a stage of the results. The target's default if empty
+    #[serde(default)]
+    pub page_sizes: Vec<u64>,
+    /// Full listings of the bucket per page size
+    #[serde(default = "default_passes")]
+    pub passes: u32,
+    /// Browses the bucket like a file system instead: lists the prefix with this delimiter,
+    /// then every common prefix it returns, all the way down
+    #[serde(default)]
+    pub delimiter: Option<String>,
+}
+
+fn default_passes() -> u32 {
+    1
+}
+
+impl QueryTest {
+    /// Page sizes to sweep, `None` for the target's default
+    pub fn page_sizes(&self) -> Vec<Option<u64>> {
+        if self.page_sizes.is_empty() {
+            return vec![None];
+        }
+        self.page_sizes.iter().copied().map(Some).collect()
+    }
+}
 
 pub trait RandomizedNetwork {
     fn random_rpc_url(&self) -> Url;
@@ -185,3 +872,147 @@ impl RandomizedNetwork for Network {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(mut upload: serde_json::Value) -> anyhow::Result<TestConfig> {
+        upload["bucket"] = serde_json::Value::Null;
+        let config = json!({
+            "funderPrivateKey": "",
+            "network": "devnet",
+            "test": {
+                "numAccounts": 1,
+                "target": "memory",
+                "upload": upload,
+                "delete": true,
+            },
+        });
+        TestConfig::from_slice(&serde_json::to_vec(&config).unwrap())
+    }
+
+    #[test]
+    fn test_upload_needs_blob_count_or_duration() {
+        let config = parse(json!({ "prefix": "foo", "blobSize": 10, "blobCount": 5 })).unwrap();
+        assert_eq!(5, config.test.upload.blob_count);
+        let config = parse(json!({ "prefix": "foo", "blobSize": 10, "durationSecs": 5 })).unwrap();
+        assert_eq!(Some(5), config.test.upload.duration_secs);
+
+        let err = parse(json!({ "prefix": "foo", "blobSize": 10 })).unwrap_err();
+        assert_eq!(
+            "upload needs a blobCount or durationSecs unless a profile is set",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_upload_rate_must_be_valid() {
+        let upload = json!({ "prefix": "foo", "blobSize": 10, "blobCount": 5, "rate": 2.5 });
+        let mut config = parse(upload).unwrap();
+        assert_eq!(Some(2.5), config.test.upload.rate);
+
+        for rate in [0.0, -1.0, 1e-9, f64::NAN, f64::INFINITY] {
+            config.test.upload.rate = Some(rate);
+            let err = config.validate().unwrap_err();
+            assert_eq!(
+                format!("the upload rate must be at least 0.001 per second, got {rate}"),
+                err.to_string()
+            );
+        }
+        let upload = json!({ "prefix": "foo", "blobSize": 10, "blobCount": 5, "rate": 0 });
+        assert!(parse(upload).is_err());
+    }
+
+    #[test]
+    fn test_profile_stages_must_be_valid() {
+        let mut config = parse(json!({ "prefix": "foo", "blobSize": 10, "blobCount": 5 })).unwrap();
+        let stage = |stage: serde_json::Value| serde_json::from_value(stage).unwrap();
+        config.test.profile = vec![
+            stage(json!({ "name": "rampUp", "durationSecs": 10, "shape": "ramp", "rate": 50 })),
+            stage(json!({ "name": "rampDown", "durationSecs": 10, "shape": "ramp", "rate": 0 })),
+        ];
+        config.validate().unwrap();
+
+        config.test.profile[1].duration_secs = 0;
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            "stage rampDown needs a durationSecs of at least 1",
+            err.to_string()
+        );
+        config.test.profile[1].duration_secs = 10;
+        for rate in [-1.0, 1e-9, f64::NAN] {
+            config.test.profile[0].rate = Some(rate);
+            let err = config.validate().unwrap_err();
+            assert_eq!(
+                format!(
+                    "the rate of stage rampUp must be 0 or at least 0.001 per second, got {rate}"
+                ),
+                err.to_string()
+            );
+        }
+    }
+
+    fn downloads(distribution: serde_json::Value, reads: u32) -> DownloadTest {
+        serde_json::from_value(json!({
+            "concurrency": 1,
+            "distribution": distribution,
+            "reads": reads,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_uniform_weights() {
+        assert_eq!(Some(vec![1.0; 3]), KeyDistribution::Uniform.weights(3));
+        assert_eq!(Some(vec![]), KeyDistribution::Uniform.weights(0));
+        assert_eq!(None, KeyDistribution::Sequential.weights(3));
+    }
+
+    #[test]
+    fn test_hotspot_weights() {
+        let hotspot = |hot_fraction, hot_reads| KeyDistribution::Hotspot {
+            hot_fraction,
+            hot_reads,
+        };
+        // One of 4 keys is hot and gets 0.8 of the reads, the other 3 share 0.2
+        let weights = hotspot(0.25, 0.8).weights(4).unwrap();
+        assert_eq!(4, weights.len());
+        assert!((weights[0] - 0.8).abs() < 1e-9);
+        for weight in &weights[1..] {
+            assert!((weight - 0.2 / 3.0).abs() < 1e-9);
+        }
+        // At least one key is hot
+        assert_eq!(Some(vec![1.0, 0.0]), hotspot(0.0, 1.0).weights(2));
+        // Every key is hot
+        assert_eq!(Some(vec![1.0; 3]), hotspot(1.0, 0.8).weights(3));
+        assert_eq!(Some(vec![1.0]), hotspot(0.2, 0.8).weights(1));
+        assert_eq!(Some(vec![]), hotspot(0.2, 0.8).weights(0));
+    }
+
+    #[test]
+    fn test_latest_weights() {
+        let latest = KeyDistribution::Latest { exponent: 1.0 };
+        assert_eq!(Some(vec![0.25, 1.0 / 3.0, 0.5, 1.0]), latest.weights(4));
+        let latest = KeyDistribution::Latest { exponent: 2.0 };
+        assert_eq!(Some(vec![0.25, 1.0]), latest.weights(2));
+        assert_eq!(Some(vec![]), latest.weights(0));
+    }
+
+    #[test]
+    fn test_pick_without_keys() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        for distribution in [
+            json!("sequential"),
+            json!("uniform"),
+            json!({ "hotspot": {} }),
+            json!({ "latest": {} }),
+        ] {
+            let picked = downloads(distribution, 5).pick(0, &mut rng).unwrap();
+            assert!(picked.is_empty());
+        }
+        let picked = downloads(json!("sequential"), 5).pick(2, &mut rng).unwrap();
+        assert_eq!(vec![0, 1, 0, 1, 0], picked);
+    }
+}
@@ -3,11 +3,12 @@
 pub mod commands;
 pub mod config;
 pub mod funder;
+pub mod payload;
 pub mod stats;
 pub mod targets;
 
-use clap::{command, Parser, Subcommand};
-use commands::{BasicTestOpts, CleanupOpts, QueryOpts, RunTestOpts};
+use clap::{Parser, Subcommand};
+use commands::{BasicTestOpts, CleanupOpts, CompareOpts, QueryOpts, ReplayOpts, RunTestOpts};
 use recall_signer::key::parse_secret_key;
 use recall_signer::{key::SecretKey, EthAddress};
 
@@ -32,6 +33,10 @@ pub enum Commands {
     #[clap(alias = "run")]
     /// Run a more sophisticated test from a config file
     RunTest(RunTestOpts),
+    /// Replay a trace recorded with --record against any target
+    Replay(ReplayOpts),
+    /// Compare two results files and exit with an error on regressions
+    Compare(CompareOpts),
 }
 
 pub(crate) fn parse_private_key(sk: &str) -> anyhow::Result<KeyData> {
@@ -3,7 +3,12 @@
 
 use std::time::Instant;
 
+use anyhow::Context as _;
 use clap::Parser as _;
+use recall_loader::commands::{
+    CircuitBreakerTripped, Interrupted, PartialFailure, RegressionError, SetupError, Signal,
+};
+use recall_loader::stats::assertions::AssertionError;
 use recall_loader::{config::TestConfig, Cli};
 use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
@@ -25,16 +30,21 @@ async fn main() -> anyhow::Result<()> {
     let start = Instant::now();
     let res = match opts.command {
         recall_loader::Commands::BasicTest(opts) => {
+            let outputs = opts.outputs.clone();
             let config = opts.into();
-            recall_loader::commands::run(config).await
+            recall_loader::commands::run(config, outputs).await
         }
         recall_loader::Commands::Cleanup(opts) => recall_loader::commands::cleanup(opts).await,
         recall_loader::Commands::RunTest(opts) => {
             let config = std::fs::read(opts.path)?;
-            let config: TestConfig = serde_json::from_slice(&config)?;
-            recall_loader::commands::run(config).await
+            match TestConfig::from_slice(&config).context(SetupError) {
+                Ok(config) => recall_loader::commands::run(config, opts.outputs).await,
+                Err(error) => Err(error),
+            }
         }
         recall_loader::Commands::Query(opts) => recall_loader::commands::query(opts).await,
+        recall_loader::Commands::Replay(opts) => recall_loader::commands::replay(opts).await,
+        recall_loader::Commands::Compare(opts) => recall_loader::commands::compare(opts).await,
     };
     let elapsed = start.elapsed();
     match res {
@@ -43,7 +53,40 @@ async fn main() -> anyhow::Result<()> {
         }
         Err(error) => {
             warn!(?error, ?elapsed, "completed with error");
+            std::process::exit(exit_code(&error));
         }
     }
     Ok(())
 }
+
+/// Exit codes, so pipelines can tell why a run failed
+const EXIT_FAILURE: i32 = 1;
+const EXIT_SETUP_FAILURE: i32 = 3;
+const EXIT_ASSERTION_FAILURE: i32 = 4;
+const EXIT_PARTIAL_FAILURE: i32 = 5;
+const EXIT_CIRCUIT_BREAKER: i32 = 6;
+/// What a shell reports for a process killed by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+/// What a shell reports for a process killed by SIGTERM
+const EXIT_TERMINATED: i32 = 143;
+
+fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.downcast_ref::<SetupError>().is_some() {
+        EXIT_SETUP_FAILURE
+    } else if error.downcast_ref::<AssertionError>().is_some()
+        || error.downcast_ref::<RegressionError>().is_some()
+    {
+        EXIT_ASSERTION_FAILURE
+    } else if error.downcast_ref::<PartialFailure>().is_some() {
+        EXIT_PARTIAL_FAILURE
+    } else if error.downcast_ref::<CircuitBreakerTripped>().is_some() {
+        EXIT_CIRCUIT_BREAKER
+    } else if let Some(interrupted) = error.downcast_ref::<Interrupted>() {
+        match interrupted.signal {
+            Signal::Interrupt => EXIT_INTERRUPTED,
+            Signal::Terminate => EXIT_TERMINATED,
+        }
+    } else {
+        EXIT_FAILURE
+    }
+}
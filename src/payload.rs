@@ -0,0 +1,237 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::{cmp, fmt, io};
+
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng as _, RngCore as _, SeedableRng as _};
+use sha2::{Digest as _, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// SHA-256 of a blob's content
+pub type Digest = [u8; 32];
+
+/// Bytes generated at a time. The rng drops the rest of a word when it fills a length that
+/// isn't a multiple of it, so generating whole blocks keeps the content independent of how
+/// it is read
+const BLOCK: usize = 4096;
+
+/// Generates `size` random bytes while they are read, so uploads of any size
+/// need neither a buffer of the full blob nor a temp file.
+pub struct RandomReader {
+    rng: StdRng,
+    block: Vec<u8>,
+    /// Bytes of `block` that were read already
+    read: usize,
+    remaining: u64,
+}
+
+impl RandomReader {
+    pub fn new(size: u64) -> Self {
+        Self::with_seed(thread_rng().gen(), size)
+    }
+
+    /// Generates the same bytes for the same `seed`
+    pub fn with_seed(seed: u64, size: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            block: vec![0; BLOCK],
+            read: BLOCK,
+            remaining: size,
+        }
+    }
+
+    /// SHA-256 of the `size` bytes a reader with `seed` generates, without keeping them
+    pub fn digest(seed: u64, size: u64) -> Digest {
+        let mut reader = Self::with_seed(seed, size);
+        let mut hasher = Sha256::new();
+        let mut buf = [0; BLOCK];
+        while reader.remaining > 0 {
+            let len = cmp::min(reader.remaining, BLOCK as u64) as usize;
+            reader.fill(&mut buf[..len]);
+            hasher.update(&buf[..len]);
+        }
+        hasher.finalize().into()
+    }
+
+    fn fill(&mut self, out: &mut [u8]) {
+        let mut filled = 0;
+        while filled < out.len() {
+            if self.read == BLOCK {
+                self.rng.fill_bytes(&mut self.block);
+                self.read = 0;
+            }
+            let len = cmp::min(BLOCK - self.read, out.len() - filled);
+            out[filled..filled + len].copy_from_slice(&self.block[self.read..self.read + len]);
+            self.read += len;
+            filled += len;
+        }
+        self.remaining -= out.len() as u64;
+    }
+}
+
+impl AsyncRead for RandomReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = cmp::min(self.remaining, buf.remaining() as u64) as usize;
+        self.fill(buf.initialize_unfilled_to(len));
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// SHA-256 state shared with a stream that is handed to a target, so the digest can be read
+/// once the target is done with the stream
+#[derive(Clone, Default)]
+pub struct StreamHash(Arc<Mutex<Sha256>>);
+
+impl StreamHash {
+    fn update(&self, data: &[u8]) {
+        self.0.lock().unwrap().update(data);
+    }
+
+    /// Digest of everything that went through the stream so far
+    pub fn digest(&self) -> Digest {
+        self.0.lock().unwrap().clone().finalize().into()
+    }
+
+    /// Checks what went through the stream against the digest of the uploaded blob
+    pub fn verify(&self, key: &str, expected: &Digest) -> Result<(), IntegrityError> {
+        let actual = self.digest();
+        if actual != *expected {
+            return Err(IntegrityError {
+                key: key.to_string(),
+                expected: *expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Hashes everything that is written to `inner`
+pub struct HashingWriter<W> {
+    inner: W,
+    hash: StreamHash,
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W, hash: StreamHash) -> Self {
+        Self { inner, hash }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = res {
+            self.hash.update(&buf[..written]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A downloaded blob doesn't have the content that was uploaded
+#[derive(Debug)]
+pub struct IntegrityError {
+    pub key: String,
+    pub expected: Digest,
+    pub actual: Digest,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "integrity check failed for {}: expected sha256 {}, got {}",
+            self.key,
+            hex::encode(self.expected),
+            hex::encode(self.actual)
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt as _;
+
+    #[tokio::test]
+    async fn test_reads_exactly_size_bytes() {
+        let mut data = Vec::new();
+        RandomReader::new(200_000)
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(200_000, data.len());
+        assert!(data.iter().any(|b| *b != 0));
+    }
+
+    #[tokio::test]
+    async fn test_seeded_content_does_not_depend_on_reads() {
+        let mut data = Vec::new();
+        RandomReader::with_seed(7, 10_000)
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        // reads that end in the middle of a word of the rng and of a block
+        let mut reader = RandomReader::with_seed(7, 10_000);
+        let mut chunks = Vec::new();
+        for len in [3, 4094, 5, 5898] {
+            let mut chunk = vec![0; len];
+            reader.read_exact(&mut chunk).await.unwrap();
+            chunks.extend(chunk);
+        }
+        assert_eq!(data, chunks);
+        assert_eq!(
+            <[u8; 32]>::from(Sha256::digest(&data)),
+            RandomReader::digest(7, 10_000)
+        );
+        assert_ne!(
+            RandomReader::digest(7, 10_000),
+            RandomReader::digest(8, 10_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hashes_what_is_written() {
+        use tokio::io::AsyncWriteExt as _;
+
+        let mut data = Vec::new();
+        RandomReader::with_seed(1, 100_000)
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        let expected = RandomReader::digest(1, 100_000);
+
+        let downloaded = StreamHash::default();
+        let mut writer = HashingWriter::new(tokio::io::sink(), downloaded.clone());
+        writer.write_all(&data).await.unwrap();
+        downloaded.verify("foo/1", &expected).unwrap();
+
+        let corrupted = StreamHash::default();
+        let mut writer = HashingWriter::new(tokio::io::sink(), corrupted.clone());
+        writer.write_all(&data[1..]).await.unwrap();
+        let err = corrupted.verify("foo/1", &expected).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("integrity check failed for foo/1"));
+    }
+}
@@ -2,90 +2,146 @@ use std::collections::{HashMap, HashSet};
 use std::{cmp, fmt};
 
 use crate::stats::ops::{Operation, OperationType, Throughput};
+use crate::stats::report::{millis, LatencySummary, OperationSummary};
 use chrono::{DateTime, Duration, Utc};
+use hdrhistogram::Histogram;
 use tracing::info;
 
+/// Latency percentiles reported for every operation type, as (label, quantile)
+pub const PERCENTILES: [(&str, f64); 5] = [
+    ("p50", 0.5),
+    ("p90", 0.9),
+    ("p95", 0.95),
+    ("p99", 0.99),
+    ("p99.9", 0.999),
+];
+
+/// Durations above a day are recorded as a day
+const MAX_TRACKED_MICROS: u64 = 24 * 60 * 60 * 1_000_000;
+
 pub struct Aggregator {
     operations: HashMap<OperationType, AggregatedOperation>,
+    /// The same stats per load profile stage, for operations that were tagged with one
+    stages: HashMap<(String, OperationType), AggregatedOperation>,
+    /// Operations that ran during the warmup of their account and were left out
+    warmup: u64,
 }
 
 impl Aggregator {
     pub fn new() -> Self {
         Aggregator {
             operations: HashMap::new(),
+            stages: HashMap::new(),
+            warmup: 0,
         }
     }
     pub fn insert(&mut self, operation: Operation) {
+        if operation.warmup {
+            self.warmup += 1;
+            return;
+        }
         let op_type = operation.op_type.clone();
+        if !operation.stage.is_empty() {
+            self.stages
+                .entry((operation.stage.clone(), op_type.clone()))
+                .or_insert_with(AggregatedOperation::new)
+                .insert(operation.clone());
+        }
         self.operations
             .entry(op_type)
-            .or_insert(AggregatedOperation {
-                start_time: DateTime::<Utc>::MAX_UTC,
-                end_time: DateTime::<Utc>::MIN_UTC,
-                min_duration: Duration::MAX,
-                max_duration: Duration::MIN,
-                ..Default::default()
-            })
+            .or_insert_with(AggregatedOperation::new)
             .insert(operation)
     }
 
+    /// Stats of the stages in the order they ran, then by operation name
+    fn sorted_stages(&self) -> Vec<(&String, &OperationType, &AggregatedOperation)> {
+        let mut first_start = HashMap::new();
+        for ((stage, _), operation) in &self.stages {
+            let start = first_start.entry(stage).or_insert(operation.start_time);
+            *start = cmp::min(*start, operation.start_time);
+        }
+        let mut stages = self
+            .stages
+            .iter()
+            .map(|((stage, op_type), operation)| (stage, op_type, operation))
+            .collect::<Vec<_>>();
+        stages.sort_by_key(|(stage, op_type, _)| (first_start[stage], op_type.to_string()));
+        stages
+    }
+
+    /// Summaries of every operation type sorted by operation name, followed by the
+    /// summaries of every load profile stage
+    pub fn summaries(&self) -> Vec<OperationSummary> {
+        let mut summaries = self
+            .operations
+            .iter()
+            .map(|(op_type, operation)| operation.summary(op_type, None))
+            .collect::<Vec<_>>();
+        summaries.sort_by(|a, b| a.operation.cmp(&b.operation));
+        summaries.extend(
+            self.sorted_stages()
+                .into_iter()
+                .map(|(stage, op_type, operation)| operation.summary(op_type, Some(stage))),
+        );
+        summaries
+    }
+
     pub fn display(&self) {
+        if self.warmup > 0 {
+            println!("Left out {} warmup operations", self.warmup);
+        }
         for (op_type, operation) in &self.operations {
-            info!(
-                operation = %op_type,
-                concurrency = operation.concurrency(),
-                duration = %HumanDuration(operation.duration()),
-                total = operation.n,
-                errors = operation.errors,
-                throughput = %operation.avg_throughput(),
-                objects_per_sec = operation.objects_per_sec(),
-                min_duration = %HumanDuration(operation.min_duration),
-                avg_duration = %HumanDuration(operation.avg_duration()),
-                max_duration = %HumanDuration(operation.max_duration),
-                "Test results"
-            );
-
-            println!("----------------------------------------------------");
-            println!(
-                "Operation: {}. Concurrency: {}. Duration: {}",
-                op_type,
-                operation.concurrency(),
-                HumanDuration(operation.duration())
-            );
-            println!("Total: {}", operation.n);
-            println!("Errors: {}", operation.errors);
-            println!();
-            println!("Averages");
-            println!("* Throughput: {}", operation.avg_throughput());
-            println!("* Objects/s: {:.1}", operation.objects_per_sec());
-            println!();
-            println!("Duration Per Operation ");
-            println!("* Min: {}", HumanDuration(operation.min_duration));
-            println!("* Avg: {}", HumanDuration(operation.avg_duration()));
-            println!("* Max: {}", HumanDuration(operation.max_duration));
-            println!();
+            operation.display(op_type, "");
+        }
+        for (stage, op_type, operation) in self.sorted_stages() {
+            operation.display(op_type, stage);
         }
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct AggregatedOperation {
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     min_duration: Duration,
     max_duration: Duration,
     errors: i32,
+    /// Errors of downloads whose content didn't match the upload, also counted in `errors`
+    integrity_errors: i32,
     n: i32,
     total_duration: Duration,
     total_bytes: i64,
     threads: HashSet<String>,
+    /// Durations of the successful operations in microseconds. The histogram has fixed bounds
+    /// and precision, so its size doesn't grow with the number of operations
+    histogram: Histogram<u64>,
 }
 
 impl AggregatedOperation {
+    pub fn new() -> Self {
+        AggregatedOperation {
+            start_time: DateTime::<Utc>::MAX_UTC,
+            end_time: DateTime::<Utc>::MIN_UTC,
+            min_duration: Duration::MAX,
+            max_duration: Duration::MIN,
+            errors: 0,
+            integrity_errors: 0,
+            n: 0,
+            total_duration: Duration::zero(),
+            total_bytes: 0,
+            threads: HashSet::new(),
+            histogram: Histogram::new_with_bounds(1, MAX_TRACKED_MICROS, 3)
+                .expect("histogram bounds are valid"),
+        }
+    }
+
     pub fn insert(&mut self, operation: Operation) {
         self.n += 1;
         if !operation.error.is_empty() {
             self.errors += 1;
+            if operation.corrupted {
+                self.integrity_errors += 1;
+            }
             // early return because we don't want operations that failed to count on stats
             return;
         }
@@ -96,12 +152,111 @@ impl AggregatedOperation {
         self.end_time = cmp::max(self.end_time, operation.end);
         self.min_duration = cmp::min(self.min_duration, operation.duration());
         self.max_duration = cmp::max(self.max_duration, operation.duration());
+        let micros = operation.duration().num_microseconds().unwrap_or(i64::MAX);
+        self.histogram.saturating_record(micros.max(0) as u64);
+    }
+
+    /// The duration that `quantile` (0.0 to 1.0) of the successful operations stayed under
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        Duration::microseconds(self.histogram.value_at_quantile(quantile) as i64)
+    }
+
+    fn successes(&self) -> i32 {
+        self.n - self.errors
     }
 
     pub fn duration(&self) -> Duration {
+        if self.successes() == 0 {
+            return Duration::zero();
+        }
         self.end_time.signed_duration_since(self.start_time)
     }
 
+    pub fn summary(&self, op_type: &OperationType, stage: Option<&str>) -> OperationSummary {
+        let latency_ms = (self.successes() > 0).then(|| LatencySummary {
+            min: millis(self.min_duration),
+            avg: millis(self.avg_duration()),
+            max: millis(self.max_duration),
+            percentiles: PERCENTILES
+                .iter()
+                .map(|(label, quantile)| (label.to_string(), millis(self.percentile(*quantile))))
+                .collect(),
+        });
+        OperationSummary {
+            operation: op_type.to_string(),
+            stage: stage.map(str::to_string),
+            concurrency: self.concurrency(),
+            duration_ms: millis(self.duration()),
+            total: self.n,
+            errors: self.errors,
+            integrity_errors: self.integrity_errors,
+            throughput_bytes_per_sec: self.avg_throughput().0,
+            objects_per_sec: self.objects_per_sec(),
+            latency_ms,
+        }
+    }
+
+    /// Prints the stats, for the whole run if `stage` is empty
+    fn display(&self, op_type: &OperationType, stage: &str) {
+        let percentiles = PERCENTILES
+            .iter()
+            .map(|(label, quantile)| {
+                format!("{label}={}", HumanDuration(self.percentile(*quantile)))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!(
+            operation = %op_type,
+            stage,
+            concurrency = self.concurrency(),
+            duration = %HumanDuration(self.duration()),
+            total = self.n,
+            errors = self.errors,
+            integrity_errors = self.integrity_errors,
+            throughput = %self.avg_throughput(),
+            objects_per_sec = self.objects_per_sec(),
+            min_duration = %HumanDuration(self.min_duration),
+            avg_duration = %HumanDuration(self.avg_duration()),
+            max_duration = %HumanDuration(self.max_duration),
+            percentiles,
+            "Test results"
+        );
+
+        println!("----------------------------------------------------");
+        let stage_label = if stage.is_empty() {
+            String::new()
+        } else {
+            format!(" Stage: {stage}.")
+        };
+        println!(
+            "Operation: {}.{} Concurrency: {}. Duration: {}",
+            op_type,
+            stage_label,
+            self.concurrency(),
+            HumanDuration(self.duration())
+        );
+        println!("Total: {}", self.n);
+        println!("Errors: {}", self.errors);
+        if self.integrity_errors > 0 {
+            println!("Integrity errors: {}", self.integrity_errors);
+        }
+        println!();
+        println!("Averages");
+        println!("* Throughput: {}", self.avg_throughput());
+        println!("* Objects/s: {:.1}", self.objects_per_sec());
+        println!();
+        println!("Duration Per Operation ");
+        println!("* Min: {}", HumanDuration(self.min_duration));
+        println!("* Avg: {}", HumanDuration(self.avg_duration()));
+        println!("* Max: {}", HumanDuration(self.max_duration));
+        println!();
+        println!("Latency Percentiles");
+        for (label, quantile) in PERCENTILES {
+            println!("* {}: {}", label, HumanDuration(self.percentile(quantile)));
+        }
+        println!();
+    }
+
     pub fn avg_throughput(&self) -> Throughput {
         if self.total_bytes == 0 {
             return Throughput(0.0);
@@ -114,6 +269,9 @@ impl AggregatedOperation {
     }
 
     pub fn objects_per_sec(&self) -> f64 {
+        if self.duration().is_zero() {
+            return 0.0;
+        }
         let d = self.duration().num_nanoseconds().unwrap() as f64;
         let one_second = Duration::seconds(1).num_nanoseconds().unwrap() as f64;
 
@@ -176,7 +334,7 @@ impl fmt::Display for HumanDuration {
 mod tests {
     use crate::stats::aggregator::{Aggregator, HumanDuration};
     use crate::stats::ops::{Operation, OperationType};
-    use chrono::DateTime;
+    use chrono::{DateTime, Duration};
 
     #[test]
     fn test_insert_new_operation_to_aggregator() {
@@ -189,6 +347,9 @@ mod tests {
             size: 10,
             file: "bar/1.txt".to_string(),
             error: "".to_string(),
+            stage: "".to_string(),
+            warmup: false,
+            corrupted: false,
         };
 
         let operation2 = Operation {
@@ -199,6 +360,9 @@ mod tests {
             size: 30,
             file: "bar/2.txt".to_string(),
             error: "".to_string(),
+            stage: "".to_string(),
+            warmup: false,
+            corrupted: false,
         };
 
         aggregator.insert(operation1);
@@ -216,7 +380,7 @@ mod tests {
         );
         assert_eq!(2f64 / 1.8, aggregated_operation.objects_per_sec());
         assert_eq!(
-            "0.800s",
+            "800ms",
             HumanDuration(aggregated_operation.min_duration).to_string()
         );
         assert_eq!(
@@ -228,4 +392,94 @@ mod tests {
             HumanDuration(aggregated_operation.max_duration).to_string()
         );
     }
+
+    #[test]
+    fn test_percentiles() {
+        let mut aggregator = Aggregator::new();
+        let start = DateTime::from_timestamp_millis(1736886531819).unwrap();
+        for i in 1..=1000 {
+            aggregator.insert(Operation {
+                id: "1".to_string(),
+                start,
+                end: start + Duration::milliseconds(i),
+                op_type: OperationType::Put,
+                size: 10,
+                file: format!("bar/{i}.txt"),
+                error: "".to_string(),
+                stage: "".to_string(),
+                warmup: false,
+                corrupted: false,
+            });
+        }
+
+        let aggregated_operation = aggregator.operations.get(&OperationType::Put).unwrap();
+        for (quantile, expected) in [(0.5, 500), (0.9, 900), (0.99, 990), (0.999, 999)] {
+            let actual = aggregated_operation.percentile(quantile).num_milliseconds();
+            assert!(
+                (actual - expected).abs() <= 1,
+                "p{quantile}: expected {expected}ms, got {actual}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn test_summaries() {
+        let mut aggregator = Aggregator::new();
+        let start = DateTime::from_timestamp_millis(1736886531819).unwrap();
+        aggregator.insert(Operation {
+            id: "1".to_string(),
+            start,
+            end: start + Duration::milliseconds(400),
+            op_type: OperationType::Put,
+            size: 10,
+            ..Default::default()
+        });
+        aggregator.insert(Operation {
+            id: "1".to_string(),
+            start,
+            end: start + Duration::milliseconds(100),
+            op_type: OperationType::Delete,
+            error: "failed".to_string(),
+            ..Default::default()
+        });
+        aggregator.insert(Operation {
+            id: "1".to_string(),
+            start,
+            end: start + Duration::milliseconds(200),
+            op_type: OperationType::Get,
+            error: "integrity check failed".to_string(),
+            corrupted: true,
+            ..Default::default()
+        });
+        aggregator.insert(Operation {
+            id: "1".to_string(),
+            start,
+            end: start + Duration::milliseconds(900),
+            op_type: OperationType::List,
+            warmup: true,
+            ..Default::default()
+        });
+
+        let summaries = aggregator.summaries();
+        assert_eq!(3, summaries.len());
+        let (delete, get, put) = (&summaries[0], &summaries[1], &summaries[2]);
+        assert_eq!("Delete", delete.operation);
+        assert_eq!(
+            (1, 1, 0),
+            (delete.total, delete.errors, delete.integrity_errors)
+        );
+        assert_eq!(0.0, delete.objects_per_sec);
+        assert!(delete.latency_ms.is_none());
+        assert_eq!("Get", get.operation);
+        assert_eq!((1, 1, 1), (get.total, get.errors, get.integrity_errors));
+        assert_eq!("Put", put.operation);
+        assert_eq!(400.0, put.duration_ms);
+        assert_eq!(25.0, put.throughput_bytes_per_sec);
+        let latency = put.latency_ms.as_ref().unwrap();
+        assert_eq!((400.0, 400.0), (latency.min, latency.max));
+        assert_eq!(
+            vec!["p50", "p90", "p95", "p99", "p99.9"],
+            latency.percentiles.keys().collect::<Vec<_>>()
+        );
+    }
 }
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::config::{AssertionsConfig, OperationAssertions};
+use crate::stats::ops::OperationType;
+use crate::stats::report::OperationSummary;
+
+/// Returned by a test run when the results break at least one of the configured assertions
+#[derive(Debug)]
+pub struct AssertionError {
+    pub failures: Vec<String>,
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} assertions failed", self.failures.len())
+    }
+}
+
+impl std::error::Error for AssertionError {}
+
+/// Checks the results of a run and returns a description of every failed assertion. Only
+/// the stats of the whole run are checked, not those of single load profile stages
+pub fn check(config: &AssertionsConfig, summaries: &[OperationSummary]) -> Vec<String> {
+    let checks = [
+        (OperationType::Put, &config.put),
+        (OperationType::Get, &config.get),
+        (OperationType::List, &config.list),
+        (OperationType::Delete, &config.delete),
+    ];
+    let mut failures = Vec::new();
+    for (op_type, assertions) in checks {
+        let Some(assertions) = assertions else {
+            continue;
+        };
+        let operation = op_type.to_string();
+        match summaries
+            .iter()
+            .find(|summary| summary.operation == operation && summary.stage.is_none())
+        {
+            Some(summary) => check_operation(assertions, summary, &mut failures),
+            None => failures.push(format!("{operation}: no operations were run")),
+        }
+    }
+    failures
+}
+
+fn check_operation(
+    assertions: &OperationAssertions,
+    summary: &OperationSummary,
+    failures: &mut Vec<String>,
+) {
+    let operation = &summary.operation;
+    if let Some(max) = assertions.max_error_rate {
+        let error_rate = summary.errors as f64 / summary.total as f64;
+        if error_rate > max {
+            failures.push(format!(
+                "{operation}: error rate {error_rate:.4} is above {max}"
+            ));
+        }
+    }
+    if let Some(min) = assertions.min_objects_per_sec {
+        if summary.objects_per_sec < min {
+            failures.push(format!(
+                "{operation}: {:.1} objects/s is below {min}",
+                summary.objects_per_sec
+            ));
+        }
+    }
+    if let Some(max) = assertions.max_p99_ms {
+        let p99 = summary
+            .latency_ms
+            .as_ref()
+            .and_then(|latency| latency.percentiles.get("p99"));
+        match p99 {
+            Some(p99) if *p99 > max => {
+                failures.push(format!("{operation}: p99 of {p99:.1}ms is above {max}ms"))
+            }
+            Some(_) => {}
+            None => failures.push(format!("{operation}: no successful operations for p99")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::report::LatencySummary;
+
+    #[test]
+    fn test_check() {
+        let summaries = vec![OperationSummary {
+            operation: "Put".to_string(),
+            stage: None,
+            concurrency: 1,
+            duration_ms: 1000.0,
+            total: 100,
+            errors: 2,
+            integrity_errors: 0,
+            throughput_bytes_per_sec: 1000.0,
+            objects_per_sec: 98.0,
+            latency_ms: Some(LatencySummary {
+                min: 1.0,
+                avg: 10.0,
+                max: 300.0,
+                percentiles: [("p99".to_string(), 250.0)].into_iter().collect(),
+            }),
+        }];
+        let passing = OperationAssertions {
+            max_error_rate: Some(0.05),
+            min_objects_per_sec: Some(50.0),
+            max_p99_ms: Some(500.0),
+        };
+        let config = AssertionsConfig {
+            put: Some(passing),
+            ..Default::default()
+        };
+        assert!(check(&config, &summaries).is_empty());
+
+        let failing = OperationAssertions {
+            max_error_rate: Some(0.01),
+            min_objects_per_sec: Some(100.0),
+            max_p99_ms: Some(200.0),
+        };
+        let config = AssertionsConfig {
+            put: Some(failing),
+            delete: Some(OperationAssertions::default()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "Put: error rate 0.0200 is above 0.01",
+                "Put: 98.0 objects/s is below 100",
+                "Put: p99 of 250.0ms is above 200ms",
+                "Delete: no operations were run",
+            ],
+            check(&config, &summaries)
+        );
+    }
+}
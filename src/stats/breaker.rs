@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use crate::config::CircuitBreakerConfig;
+use crate::stats::ops::{Operation, OperationType};
+
+/// Watches every finished operation and cancels `stop` once an operation type keeps
+/// failing, so a broken network doesn't go on burning gas until the end of the run
+pub struct Breaker {
+    config: CircuitBreakerConfig,
+    stop: CancellationToken,
+    windows: HashMap<OperationType, Window>,
+    /// Why the breaker tripped, it only trips once
+    tripped: Option<String>,
+}
+
+/// Outcomes of the last operations of one type
+#[derive(Default)]
+struct Window {
+    /// Whether each operation failed, oldest first
+    failed: VecDeque<bool>,
+    errors: usize,
+    consecutive_errors: u32,
+}
+
+impl Breaker {
+    pub fn new(config: CircuitBreakerConfig, stop: CancellationToken) -> Self {
+        Self {
+            config,
+            stop,
+            windows: HashMap::new(),
+            tripped: None,
+        }
+    }
+
+    /// Counts a finished operation and trips if its type broke a limit
+    pub fn observe(&mut self, op: &Operation) {
+        if self.tripped.is_some() {
+            return;
+        }
+        let size = self.config.window;
+        let window = self.windows.entry(op.op_type.clone()).or_default();
+        let failed = !op.error.is_empty();
+        window.failed.push_back(failed);
+        if failed {
+            window.errors += 1;
+            window.consecutive_errors += 1;
+        } else {
+            window.consecutive_errors = 0;
+        }
+        if window.failed.len() > size && window.failed.pop_front() == Some(true) {
+            window.errors -= 1;
+        }
+
+        let error_rate = window.errors as f64 / window.failed.len() as f64;
+        let config = &self.config;
+        let reason = match (config.max_consecutive_errors, config.max_error_rate) {
+            (Some(max), _) if window.consecutive_errors >= max => format!(
+                "{} {} operations failed in a row",
+                window.consecutive_errors, op.op_type
+            ),
+            (_, Some(max)) if window.failed.len() == size && error_rate > max => format!(
+                "{:.1}% of the last {size} {} operations failed",
+                error_rate * 100.0,
+                op.op_type
+            ),
+            _ => return,
+        };
+        let reason = format!("{reason}, the last with: {}", op.error);
+        error!(reason, "circuit breaker tripped, stopping the test runners");
+        self.tripped = Some(reason);
+        self.stop.cancel();
+    }
+
+    /// Why the breaker stopped the run, if it did
+    pub fn tripped(&self) -> Option<&str> {
+        self.tripped.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(op_type: OperationType, failed: bool) -> Operation {
+        Operation {
+            op_type,
+            error: if failed { "timed out" } else { "" }.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_trips_on_consecutive_errors() {
+        let config = CircuitBreakerConfig {
+            max_error_rate: None,
+            window: 100,
+            max_consecutive_errors: Some(3),
+        };
+        let stop = CancellationToken::new();
+        let mut breaker = Breaker::new(config, stop.clone());
+        for failed in [true, true, false, true, true] {
+            breaker.observe(&op(OperationType::Put, failed));
+        }
+        // counted per operation type
+        breaker.observe(&op(OperationType::Get, true));
+        assert!(!stop.is_cancelled());
+
+        breaker.observe(&op(OperationType::Put, true));
+        assert!(stop.is_cancelled());
+        assert_eq!(
+            Some("3 Put operations failed in a row, the last with: timed out"),
+            breaker.tripped()
+        );
+    }
+
+    #[test]
+    fn test_trips_on_error_rate() {
+        let config = CircuitBreakerConfig {
+            max_error_rate: Some(0.5),
+            window: 4,
+            max_consecutive_errors: None,
+        };
+        let stop = CancellationToken::new();
+        let mut breaker = Breaker::new(config, stop.clone());
+        // the window isn't full yet
+        for failed in [true, true, false] {
+            breaker.observe(&op(OperationType::Delete, failed));
+        }
+        assert!(!stop.is_cancelled());
+
+        // the oldest errors drop out of the window, at most half of it fails
+        for failed in [false, false, true, true] {
+            breaker.observe(&op(OperationType::Delete, failed));
+        }
+        assert!(!stop.is_cancelled());
+        breaker.observe(&op(OperationType::Delete, true));
+        assert!(stop.is_cancelled());
+        assert_eq!(
+            Some("75.0% of the last 4 Delete operations failed, the last with: timed out"),
+            breaker.tripped()
+        );
+    }
+}
@@ -4,12 +4,15 @@ use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
 use crate::stats::aggregator::Aggregator;
+use crate::stats::breaker::Breaker;
 use crate::stats::ops::Operation;
+use crate::stats::report::OperationSummary;
 
 // Collector collects the result of each executed operation
 pub struct Collector {
     ops: Arc<Mutex<Vec<Operation>>>,
     aggregator: Arc<Mutex<Aggregator>>,
+    breaker: Option<Arc<Mutex<Breaker>>>,
     sender: Option<mpsc::Sender<Operation>>,
     background_task: Option<JoinHandle<()>>,
 }
@@ -22,14 +25,29 @@ impl Default for Collector {
 
 impl Collector {
     pub fn new() -> Self {
+        Self::start(None)
+    }
+
+    /// A collector that passes every operation to `breaker`, to stop the run early
+    pub fn with_breaker(breaker: Breaker) -> Self {
+        Self::start(Some(breaker))
+    }
+
+    fn start(breaker: Option<Breaker>) -> Self {
         let (sender, mut receiver) = mpsc::channel::<Operation>(100);
         let ops = Arc::new(Mutex::new(Vec::with_capacity(100)));
         let aggregator = Arc::new(Mutex::new(Aggregator::new()));
+        let breaker = breaker.map(|breaker| Arc::new(Mutex::new(breaker)));
 
         let ops_clone = ops.clone();
         let aggregator_clone = aggregator.clone();
+        let breaker_clone = breaker.clone();
         let background_task = tokio::spawn(async move {
             while let Some(op) = receiver.recv().await {
+                if let Some(breaker) = &breaker_clone {
+                    breaker.lock().unwrap().observe(&op);
+                }
+
                 let mut ops_guard = ops_clone.lock().unwrap();
                 ops_guard.push(op.clone());
 
@@ -41,6 +59,7 @@ impl Collector {
         Collector {
             ops,
             aggregator,
+            breaker,
             sender: Some(sender),
             background_task: Some(background_task),
         }
@@ -74,6 +93,17 @@ impl Collector {
     pub fn display_aggregated(&self) {
         self.aggregator.lock().unwrap().display();
     }
+
+    /// Why the circuit breaker stopped the run, if it did
+    pub fn tripped(&self) -> Option<String> {
+        let breaker = self.breaker.as_ref()?.lock().unwrap();
+        breaker.tripped().map(str::to_string)
+    }
+
+    /// Aggregated stats per operation type
+    pub fn summaries(&self) -> Vec<OperationSummary> {
+        self.aggregator.lock().unwrap().summaries()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::SecondsFormat;
+
+use crate::stats::ops::Operation;
+use crate::stats::report::millis;
+
+/// File format of the raw operations export
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum OpsFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// One exported operation. Times are RFC 3339 in UTC with microseconds
+#[derive(Debug, serde::Serialize)]
+struct OperationRow<'a> {
+    thread_id: &'a str,
+    op_type: String,
+    key: &'a str,
+    size: i64,
+    start: String,
+    end: String,
+    duration_ms: f64,
+    error: &'a str,
+    stage: &'a str,
+    warmup: bool,
+    corrupted: bool,
+}
+
+impl<'a> From<&'a Operation> for OperationRow<'a> {
+    fn from(op: &'a Operation) -> Self {
+        OperationRow {
+            thread_id: &op.id,
+            op_type: op.op_type.to_string(),
+            key: &op.file,
+            size: op.size,
+            start: op.start.to_rfc3339_opts(SecondsFormat::Micros, true),
+            end: op.end.to_rfc3339_opts(SecondsFormat::Micros, true),
+            duration_ms: millis(op.duration()),
+            error: &op.error,
+            stage: &op.stage,
+            warmup: op.warmup,
+            corrupted: op.corrupted,
+        }
+    }
+}
+
+/// Writes every operation to `path`, one row per operation
+pub fn write_operations(path: &Path, format: OpsFormat, ops: &[Operation]) -> Result<()> {
+    let file = File::create(path)?;
+    match format {
+        OpsFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for op in ops {
+                writer.serialize(OperationRow::from(op))?;
+            }
+            writer.flush()?;
+        }
+        OpsFormat::Jsonl => {
+            let mut writer = BufWriter::new(file);
+            for op in ops {
+                serde_json::to_writer(&mut writer, &OperationRow::from(op))?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ops::OperationType;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_write_operations() {
+        let ops = vec![
+            Operation {
+                id: "1".to_string(),
+                start: DateTime::from_timestamp_millis(1736886531819).unwrap(),
+                end: DateTime::from_timestamp_millis(1736886532619).unwrap(),
+                op_type: OperationType::Put,
+                size: 10,
+                file: "bar/1.txt".to_string(),
+                error: "".to_string(),
+                stage: "".to_string(),
+                warmup: true,
+                corrupted: false,
+            },
+            Operation {
+                id: "2".to_string(),
+                start: DateTime::from_timestamp_millis(1736886531989).unwrap(),
+                end: DateTime::from_timestamp_millis(1736886532000).unwrap(),
+                op_type: OperationType::HttpTtfb,
+                size: 0,
+                file: "bar/2.txt".to_string(),
+                error: "timed out, again".to_string(),
+                stage: "spike".to_string(),
+                warmup: false,
+                corrupted: true,
+            },
+        ];
+        let dir = std::env::temp_dir();
+
+        let path = dir.join(format!("ops-{}.csv", std::process::id()));
+        write_operations(&path, OpsFormat::Csv, &ops).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            "thread_id,op_type,key,size,start,end,duration_ms,error,stage,warmup,corrupted\n\
+             1,Put,bar/1.txt,10,2025-01-14T20:28:51.819000Z,2025-01-14T20:28:52.619000Z,800.0,,,true,false\n\
+             2,HTTP TTFB,bar/2.txt,0,2025-01-14T20:28:51.989000Z,2025-01-14T20:28:52.000000Z,11.0,\"timed out, again\",spike,false,true\n",
+            csv
+        );
+
+        let path = dir.join(format!("ops-{}.jsonl", std::process::id()));
+        write_operations(&path, OpsFormat::Jsonl, &ops).unwrap();
+        let jsonl = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(2, rows.len());
+        assert_eq!("HTTP TTFB", rows[1]["op_type"]);
+        assert_eq!(11.0, rows[1]["duration_ms"]);
+    }
+}
@@ -1,3 +1,8 @@
 mod aggregator;
+pub mod assertions;
+pub mod breaker;
 pub mod collector;
+pub mod export;
 pub mod ops;
+pub mod report;
+pub mod warmup;
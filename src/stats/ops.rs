@@ -31,6 +31,16 @@ pub enum OperationType {
     Put,
     List,
     Delete,
+    /// Time from the commit of an upload until the blob could be downloaded for the first time
+    Resolve,
+    /// Time from broadcasting an upload until its transaction was included in a block
+    Commit,
+    /// Time to open the connection (TCP and TLS) to the objects API
+    HttpConnect,
+    /// Time from sending a request to the objects API until the response headers arrive
+    HttpTtfb,
+    /// Time to stream a response body from the objects API
+    HttpTransfer,
 }
 
 impl fmt::Display for OperationType {
@@ -40,6 +50,11 @@ impl fmt::Display for OperationType {
             OperationType::Put => "Put",
             OperationType::List => "List",
             OperationType::Delete => "Delete",
+            OperationType::Resolve => "Resolve",
+            OperationType::Commit => "Commit",
+            OperationType::HttpConnect => "HTTP Connect",
+            OperationType::HttpTtfb => "HTTP TTFB",
+            OperationType::HttpTransfer => "HTTP Transfer",
         };
         write!(f, "{}", operation)
     }
@@ -54,6 +69,12 @@ pub struct Operation {
     pub size: i64,
     pub file: String,
     pub error: String,
+    /// Load profile stage the operation was issued in, empty outside of profiles
+    pub stage: String,
+    /// Ran during the warmup of its account and is left out of the aggregated results
+    pub warmup: bool,
+    /// Downloaded content didn't match the digest of the upload
+    pub corrupted: bool,
 }
 
 impl Operation {
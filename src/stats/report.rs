@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest as _, Sha256};
+
+use crate::config::{Target, TestConfig};
+
+/// Summary of a test run written by `--results-out`, for CI jobs and other tools
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunReport {
+    pub metadata: RunMetadata,
+    pub operations: Vec<OperationSummary>,
+    /// Assertions of the test config that the results broke
+    #[serde(default)]
+    pub failed_assertions: Vec<String>,
+    /// How well the scheduler kept the upload rate, for open loop and profile runs. Profile
+    /// runs have one entry per stage
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleSummary>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunMetadata {
+    pub network: String,
+    pub target: Target,
+    /// SHA-256 of the test config, to tell whether two runs used the same scenario
+    pub config_hash: String,
+    pub loader_version: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    /// Whether the run was stopped by a signal, so the results only cover part of it
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Why the circuit breaker stopped the run early, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<String>,
+}
+
+impl RunMetadata {
+    /// Captures the metadata of a run that starts now. `ended_at` is set by [RunMetadata::finish]
+    pub fn start(config: &TestConfig) -> Result<Self> {
+        let config_hash = Sha256::digest(serde_json::to_vec(&config.test)?);
+        let now = Utc::now();
+        Ok(Self {
+            network: config.network.to_string(),
+            target: config.test.target,
+            config_hash: hex::encode(config_hash),
+            loader_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: now,
+            ended_at: now,
+            interrupted: false,
+            circuit_breaker: None,
+        })
+    }
+
+    pub fn finish(&mut self) {
+        self.ended_at = Utc::now();
+    }
+}
+
+/// Aggregated stats of one operation type. Durations are in milliseconds
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationSummary {
+    pub operation: String,
+    /// Load profile stage the stats are limited to, missing for the stats of the whole run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    pub concurrency: i32,
+    pub duration_ms: f64,
+    pub total: i32,
+    pub errors: i32,
+    /// Errors of downloads whose content didn't match the upload, also counted in `errors`
+    #[serde(default)]
+    pub integrity_errors: i32,
+    pub throughput_bytes_per_sec: f64,
+    pub objects_per_sec: f64,
+    /// Latency of the successful operations, missing if every operation failed
+    pub latency_ms: Option<LatencySummary>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencySummary {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    /// Keyed by label, e.g. `p99.9`
+    pub percentiles: BTreeMap<String, f64>,
+}
+
+/// Offered against achieved load of an open loop run or of one load profile stage
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleSummary {
+    /// Load profile stage, missing for open loop runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    /// Average uploads per second the scheduler tried to issue, missing if every active
+    /// account uploaded one blob after another
+    pub target_rate: Option<f64>,
+    /// Successful uploads per second
+    pub achieved_rate: f64,
+    pub issued: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// How late the scheduler issued uploads compared to their slot
+    pub avg_lag_ms: f64,
+    pub max_lag_ms: f64,
+    /// Most uploads that were in flight at the same time
+    pub max_queue_depth: u64,
+}
+
+impl ScheduleSummary {
+    pub fn count(&mut self, succeeded: bool) {
+        if succeeded {
+            self.completed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    /// How far the achieved rate fell short of the target, in percent
+    pub fn shortfall(&self) -> Option<f64> {
+        let target_rate = self.target_rate.filter(|rate| *rate > 0.0)?;
+        Some(((1.0 - self.achieved_rate / target_rate) * 100.0).max(0.0))
+    }
+
+    pub fn display(&self) {
+        println!("----------------------------------------------------");
+        match &self.stage {
+            Some(stage) => println!("Stage: {stage}"),
+            None => println!("Open loop uploads"),
+        }
+        println!("Issued: {}", self.issued);
+        println!("Completed: {}", self.completed);
+        println!("Failed: {}", self.failed);
+        println!();
+        println!("Rate");
+        match (self.target_rate, self.shortfall()) {
+            (Some(target_rate), Some(shortfall)) => {
+                println!("* Target: {target_rate:.1}/s");
+                println!(
+                    "* Achieved: {:.1}/s ({shortfall:.1}% short)",
+                    self.achieved_rate
+                );
+            }
+            _ => println!("* Achieved: {:.1}/s", self.achieved_rate),
+        }
+        println!();
+        println!("Scheduler");
+        println!("* Avg lag: {:.1}ms", self.avg_lag_ms);
+        println!("* Max lag: {:.1}ms", self.max_lag_ms);
+        println!("* Max queue depth: {}", self.max_queue_depth);
+        println!();
+    }
+}
+
+impl RunReport {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+pub(crate) fn millis(duration: Duration) -> f64 {
+    duration
+        .num_microseconds()
+        .map(|micros| micros as f64 / 1000.0)
+        .unwrap_or(f64::MAX)
+}
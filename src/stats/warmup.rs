@@ -0,0 +1,98 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::config::WarmupConfig;
+
+tokio::task_local! {
+    /// Whether the operation the task is running belongs to the warmup
+    static OPERATION_WARMUP: bool;
+}
+
+/// Tracks whether a runner is still warming up. Shared by everything that records operations
+/// for the runner, so they can be tagged and left out of the aggregated results
+#[derive(Debug, Default)]
+pub struct Warmup {
+    config: Option<WarmupConfig>,
+    /// When the first operation of the runner started
+    started: OnceLock<Instant>,
+    operations: AtomicU32,
+}
+
+impl Warmup {
+    pub fn new(config: Option<WarmupConfig>) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Whether an operation that starts now belongs to the warmup. Counts the operation
+    /// towards a warmup of a number of operations
+    pub fn begin(&self) -> bool {
+        match self.config {
+            Some(WarmupConfig::Operations(_)) => {
+                let started = self.operations.fetch_add(1, Ordering::SeqCst);
+                self.within(started)
+            }
+            _ => self.active(),
+        }
+    }
+
+    /// Whether the runner is still warming up, without counting an operation. For the
+    /// parts of an operation that are recorded separately
+    pub fn active(&self) -> bool {
+        self.within(self.operations.load(Ordering::SeqCst))
+    }
+
+    /// Runs `operation` with the warmup flag its caller got from [Warmup::begin], for the
+    /// targets that record parts of it separately
+    pub async fn scope<F: Future>(warmup: bool, operation: F) -> F::Output {
+        OPERATION_WARMUP.scope(warmup, operation).await
+    }
+
+    /// Whether the operation running on this task belongs to the warmup, false outside of
+    /// [Warmup::scope]
+    pub fn operation() -> bool {
+        OPERATION_WARMUP.try_with(|warmup| *warmup).unwrap_or(false)
+    }
+
+    fn within(&self, operations: u32) -> bool {
+        match self.config {
+            None => false,
+            Some(WarmupConfig::Operations(warmup)) => operations < warmup,
+            Some(WarmupConfig::DurationSecs(secs)) => {
+                self.started.get_or_init(Instant::now).elapsed() < Duration::from_secs(secs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warmup() {
+        let warmup = Warmup::new(Some(WarmupConfig::Operations(2)));
+        assert!(warmup.begin());
+        assert!(warmup.active());
+        assert!(warmup.begin());
+        assert!(!warmup.active());
+        assert!(!warmup.begin());
+
+        let warmup = Warmup::new(Some(WarmupConfig::DurationSecs(0)));
+        assert!(!warmup.begin());
+        let warmup = Warmup::new(Some(WarmupConfig::DurationSecs(60)));
+        assert!(warmup.begin());
+        assert!(!Warmup::new(None).begin());
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(!Warmup::operation());
+        assert!(Warmup::scope(true, async { Warmup::operation() }).await);
+        assert!(!Warmup::scope(false, async { Warmup::operation() }).await);
+    }
+}
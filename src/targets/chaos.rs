@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::config::{Broadcast, ChaosConfig, ChaosOp};
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use rand::{thread_rng, Rng as _};
+use recall_sdk::machine::bucket::Bucket;
+use tokio::io::AsyncWrite;
+use tracing::debug;
+
+/// Wraps another [Target] and injects delays, timeouts, truncated downloads and errors
+/// according to a [ChaosConfig], so failure handling can be tested without breaking a network.
+pub struct ChaosTarget {
+    inner: Arc<dyn Target>,
+    config: ChaosConfig,
+}
+
+impl ChaosTarget {
+    pub fn new(inner: Arc<dyn Target>, config: ChaosConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Wraps `target` if chaos is configured, otherwise returns it as is
+    pub fn wrap(target: Arc<dyn Target>, config: &Option<ChaosConfig>) -> Arc<dyn Target> {
+        match config {
+            Some(config) => Arc::new(Self::new(target, config.clone())),
+            None => target,
+        }
+    }
+
+    async fn inject<T>(
+        &self,
+        op: &ChaosOp,
+        name: &str,
+        call: impl Future<Output = Result<T>> + Send,
+    ) -> Result<T> {
+        let delay = {
+            let mut rng = thread_rng();
+            op.delay_ms + rng.gen_range(0..=op.jitter_ms)
+        };
+        if delay > 0 {
+            debug!(delay, "chaos: delaying {name}");
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+        if hit(op.error_rate) {
+            bail!("chaos: injected {name} error");
+        }
+        if op.timeout_ms == 0 {
+            return call.await;
+        }
+        tokio::time::timeout(Duration::from_millis(op.timeout_ms), call)
+            .await
+            .map_err(|_| anyhow!("chaos: {name} timed out after {}ms", op.timeout_ms))?
+    }
+}
+
+fn hit(rate: f64) -> bool {
+    rate > 0.0 && thread_rng().gen_bool(rate.min(1.0))
+}
+
+/// Accepts half of the first write and fails every write after that, like a connection that
+/// drops in the middle of a response body
+struct TruncatingWriter {
+    inner: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+    truncated: bool,
+}
+
+impl AsyncWrite for TruncatingWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.truncated {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "chaos: download truncated",
+            )));
+        }
+        let len = (buf.len() / 2).max(1);
+        let res = Pin::new(&mut self.inner).poll_write(cx, &buf[..len]);
+        if matches!(res, Poll::Ready(Ok(_))) {
+            self.truncated = true;
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Target for ChaosTarget {
+    async fn create_bucket(&self) -> Result<Bucket> {
+        self.inner.create_bucket().await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        let call = self.inner.list_objects(bucket, prefix, start_key, limit);
+        self.inject(&self.config.list, "list", call).await
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        let call = self
+            .inner
+            .list_directory(bucket, prefix, delimiter, start_key, limit);
+        self.inject(&self.config.list, "list", call).await
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        let call = self
+            .inner
+            .add_object(bucket, key, payload, metadata, overwrite, broadcast_mode);
+        self.inject(&self.config.put, "put", call).await
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        let writer: Box<dyn AsyncWrite + Unpin + Send + 'static> =
+            if hit(self.config.get.truncate_rate) {
+                Box::new(TruncatingWriter {
+                    inner: writer,
+                    truncated: false,
+                })
+            } else {
+                writer
+            };
+        let call = self.inner.get_object(bucket, key, writer, range);
+        self.inject(&self.config.get, "get", call).await
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        let call = self.inner.delete_object(bucket, key);
+        self.inject(&self.config.delete, "delete", call).await
+    }
+
+    async fn resync(&self) -> Result<()> {
+        self.inner.resync().await
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        self.inner.inclusion(hash).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MemoryConfig, MemoryOp};
+    use crate::targets::memory::InMemoryTarget;
+
+    async fn setup(memory: MemoryConfig, chaos: ChaosConfig) -> (ChaosTarget, Bucket) {
+        let target = ChaosTarget::new(Arc::new(InMemoryTarget::new(memory)), chaos);
+        let bucket = target.create_bucket().await.unwrap();
+        target
+            .inner
+            .add_object(
+                &bucket,
+                "foo/1",
+                Payload::new(std::io::Cursor::new(vec![7; 64]), 64),
+                HashMap::new(),
+                true,
+                Broadcast::Commit,
+            )
+            .await
+            .unwrap();
+        (target, bucket)
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_timeouts() {
+        let memory = MemoryConfig {
+            list: MemoryOp {
+                latency_ms: 200,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let chaos = ChaosConfig {
+            delete: ChaosOp {
+                error_rate: 1.0,
+                ..Default::default()
+            },
+            list: ChaosOp {
+                timeout_ms: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (target, bucket) = setup(memory, chaos).await;
+
+        let err = target.delete_object(&bucket, "foo/1").await.unwrap_err();
+        assert_eq!("chaos: injected delete error", err.to_string());
+        let err = target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap_err();
+        assert_eq!("chaos: list timed out after 10ms", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_download() {
+        let chaos = ChaosConfig {
+            get: ChaosOp {
+                truncate_rate: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (target, bucket) = setup(MemoryConfig::default(), chaos).await;
+
+        let err = target
+            .get_object(&bucket, "foo/1", Box::new(tokio::io::sink()), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+}
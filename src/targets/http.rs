@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::Broadcast;
+use crate::stats::collector::Collector;
+use crate::stats::ops::{Operation, OperationType};
+use crate::stats::warmup::Warmup;
+use crate::targets::sdk::SdkTarget;
+use crate::targets::sequencer::SequencedWrites;
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt as _, Empty};
+use hyper::body::Bytes;
+use hyper::header::{HOST, RANGE};
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+use url::Url;
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// Reads straight from the objects API over plain HTTP, bypassing `recall_sdk`, and records
+/// connect, time to first byte and transfer time of every request as separate operations.
+/// Each request opens a new connection so the connect time is always part of the measurement.
+/// Writes still need signed transactions and go through the SDK.
+pub struct HttpTarget {
+    objects_api: Url,
+    sdk: SdkTarget,
+    client: HttpClient,
+    collector: Option<Arc<Collector>>,
+    thread_id: String,
+}
+
+/// Wall clock times of the phase boundaries of a single request
+pub(crate) struct Timings {
+    start: DateTime<Utc>,
+    connected: DateTime<Utc>,
+    first_byte: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// Body of `GET /v1/objects/{address}?prefix=..&start_key=..`
+#[derive(serde::Deserialize)]
+struct ListResponse {
+    objects: Vec<ListObject>,
+    /// Only set if the request had a delimiter
+    #[serde(default)]
+    common_prefixes: Vec<String>,
+    next_key: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListObject {
+    key: String,
+}
+
+/// HTTP/1 client that opens a new connection for every request, so the connect time is
+/// part of every measurement
+pub(crate) struct HttpClient {
+    tls: TlsConnector,
+}
+
+impl HttpClient {
+    pub fn new() -> Result<Self> {
+        let roots =
+            rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        Ok(Self {
+            tls: TlsConnector::from(Arc::new(tls)),
+        })
+    }
+
+    /// Sends a GET on a fresh connection and streams the body into `writer`
+    pub async fn get(
+        &self,
+        url: &Url,
+        range: Option<String>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<(Timings, i64)> {
+        let host = url.host_str().ok_or_else(|| anyhow!("no host in {url}"))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("no port for {url}"))?;
+
+        let start = Utc::now();
+        let tcp = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("failed to connect to {host}:{port}"))?;
+        let io: Box<dyn Io> = if url.scheme() == "https" {
+            let server_name = ServerName::try_from(host.to_string())?;
+            Box::new(self.tls.connect(server_name, tcp).await?)
+        } else {
+            Box::new(tcp)
+        };
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!(?err, "http connection closed");
+            }
+        });
+        let connected = Utc::now();
+
+        let mut request = Request::get(&url[url::Position::BeforePath..]).header(
+            HOST,
+            &url[url::Position::BeforeHost..url::Position::AfterPort],
+        );
+        if let Some(range) = range {
+            request = request.header(
+                RANGE,
+                format!("bytes={}", range.trim_start_matches("bytes=")),
+            );
+        }
+        let response = sender
+            .send_request(request.body(Empty::<Bytes>::new())?)
+            .await?;
+        let first_byte = Utc::now();
+
+        let status = response.status();
+        let mut body = response.into_body();
+        if !status.is_success() {
+            let message = body.collect().await?.to_bytes();
+            bail!(
+                "{host} returned {status}: {}",
+                String::from_utf8_lossy(&message)
+            );
+        }
+
+        let mut size = 0;
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame?.into_data() {
+                size += data.len() as i64;
+                writer.write_all(&data).await?;
+            }
+        }
+        writer.flush().await?;
+        let end = Utc::now();
+
+        Ok((
+            Timings {
+                start,
+                connected,
+                first_byte,
+                end,
+            },
+            size,
+        ))
+    }
+}
+
+impl HttpTarget {
+    pub fn new(
+        objects_api: Url,
+        sdk: SdkTarget,
+        collector: Option<Arc<Collector>>,
+        thread_id: String,
+    ) -> Result<Self> {
+        Ok(Self {
+            objects_api,
+            sdk,
+            client: HttpClient::new()?,
+            collector,
+            thread_id,
+        })
+    }
+
+    fn object_url(&self, bucket: &Bucket, key: Option<&str>) -> Result<Url> {
+        let mut url = self.objects_api.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("invalid objects api url {}", self.objects_api))?;
+            segments
+                .pop_if_empty()
+                .extend(["v1", "objects", &bucket.address().to_string()]);
+            if let Some(key) = key {
+                segments.extend(key.split('/'));
+            }
+        }
+        Ok(url)
+    }
+
+    async fn record(&self, key: &str, timings: &Timings, size: i64) -> Result<()> {
+        debug!(
+            key,
+            connect_ms = (timings.connected - timings.start).num_milliseconds(),
+            ttfb_ms = (timings.first_byte - timings.connected).num_milliseconds(),
+            transfer_ms = (timings.end - timings.first_byte).num_milliseconds(),
+            "objects api request"
+        );
+        let Some(collector) = &self.collector else {
+            return Ok(());
+        };
+
+        let phases = [
+            (
+                OperationType::HttpConnect,
+                timings.start,
+                timings.connected,
+                0,
+            ),
+            (
+                OperationType::HttpTtfb,
+                timings.connected,
+                timings.first_byte,
+                0,
+            ),
+            (
+                OperationType::HttpTransfer,
+                timings.first_byte,
+                timings.end,
+                size,
+            ),
+        ];
+        let warmup = Warmup::operation();
+        for (op_type, start, end, size) in phases {
+            collector
+                .collect(Operation {
+                    id: self.thread_id.clone(),
+                    start,
+                    end,
+                    op_type,
+                    size,
+                    file: key.to_string(),
+                    warmup,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Target for HttpTarget {
+    async fn create_bucket(&self) -> Result<Bucket> {
+        self.sdk.create_bucket().await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        let mut url = self.object_url(bucket, None)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("prefix", prefix);
+            if let Some(start_key) = start_key {
+                query.append_pair("start_key", &String::from_utf8(start_key)?);
+            }
+            if let Some(limit) = limit {
+                query.append_pair("limit", &limit.to_string());
+            }
+        }
+
+        let mut body = Vec::new();
+        let (timings, size) = self.client.get(&url, None, &mut body).await?;
+        self.record(prefix, &timings, size).await?;
+
+        let response: ListResponse =
+            serde_json::from_slice(&body).context("failed to parse list response")?;
+        let keys = response
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        Ok((keys, response.next_key.map(String::into_bytes)))
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        let mut url = self.object_url(bucket, None)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("prefix", prefix);
+            query.append_pair("delimiter", delimiter);
+            if let Some(start_key) = start_key {
+                query.append_pair("start_key", &String::from_utf8(start_key)?);
+            }
+            if let Some(limit) = limit {
+                query.append_pair("limit", &limit.to_string());
+            }
+        }
+
+        let mut body = Vec::new();
+        let (timings, size) = self.client.get(&url, None, &mut body).await?;
+        self.record(prefix, &timings, size).await?;
+
+        let response: ListResponse =
+            serde_json::from_slice(&body).context("failed to parse list response")?;
+        Ok(ListPage {
+            keys: response
+                .objects
+                .into_iter()
+                .map(|object| object.key)
+                .collect(),
+            common_prefixes: response.common_prefixes,
+            next_key: response.next_key.map(String::into_bytes),
+        })
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        self.sdk
+            .add_object(bucket, key, payload, metadata, overwrite, broadcast_mode)
+            .await
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        mut writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        let url = self.object_url(bucket, Some(key))?;
+        let (timings, size) = self.client.get(&url, range, &mut writer).await?;
+        writer.shutdown().await?;
+        self.record(key, &timings, size).await
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        self.sdk.delete_object(bucket, key).await
+    }
+
+    async fn resync(&self) -> Result<()> {
+        self.sdk.resync().await
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        self.sdk.inclusion(hash).await
+    }
+
+    fn sequenced(&self) -> Option<&dyn SequencedWrites> {
+        self.sdk.sequenced()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RandomizedNetwork as _;
+    use crate::targets::stub_server::{Response, StubServer};
+    use recall_provider::fvm_shared::address::Address;
+    use recall_provider::json_rpc::JsonRpcProvider;
+    use recall_sdk::network::Network;
+    use recall_signer::key::random_secretkey;
+    use recall_signer::{AccountKind, Wallet};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt as _;
+
+    fn target(server: &StubServer, collector: Option<Arc<Collector>>) -> HttpTarget {
+        let network = Network::Localnet;
+        let network_cfg = network.get_config();
+        let provider =
+            JsonRpcProvider::new_http(network_cfg.rpc_url.clone(), network.chain_id(), None, None)
+                .unwrap();
+        let sk = random_secretkey();
+        let wallet = Wallet::new_secp256k1(
+            sk.clone(),
+            AccountKind::Ethereum,
+            network_cfg.subnet_id.clone(),
+        )
+        .unwrap();
+        let sdk = SdkTarget {
+            provider,
+            wallet,
+            sk,
+            subnet_id: network_cfg.subnet_id,
+            rpc_url: network_cfg.rpc_url.to_string().parse().unwrap(),
+            client: HttpClient::new().unwrap(),
+        };
+        HttpTarget::new(
+            server.url().parse().unwrap(),
+            sdk,
+            collector,
+            "1".to_string(),
+        )
+        .unwrap()
+    }
+
+    async fn bucket() -> Bucket {
+        Bucket::attach(Address::new_id(1001)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_records_phases() {
+        let server = StubServer::start(|_| Response {
+            first_byte_delay: Duration::from_millis(50),
+            body_delay: Duration::from_millis(50),
+            ..Response::new(206, "0123")
+        })
+        .await;
+        let collector = Arc::new(Collector::new());
+        let target = target(&server, Some(collector.clone()));
+        let bucket = bucket().await;
+
+        let (writer, mut reader) = tokio::io::duplex(64);
+        // the phases belong to the warmup of the get they are part of
+        let get = target.get_object(&bucket, "foo/1", Box::new(writer), Some("2-5".to_string()));
+        Warmup::scope(true, get).await.unwrap();
+        let mut body = String::new();
+        reader.read_to_string(&mut body).await.unwrap();
+        assert_eq!("0123", body);
+
+        let request = &server.requests()[0];
+        assert_eq!("GET", request.method);
+        assert_eq!(
+            format!("/v1/objects/{}/foo/1", bucket.address()),
+            request.path
+        );
+        assert_eq!(Some("bytes=2-5"), request.header("range"));
+
+        drop(target);
+        let mut collector = Arc::try_unwrap(collector).ok().unwrap();
+        collector.close().await;
+        let ops = collector.get_operations();
+        let types: Vec<_> = ops.iter().map(|op| op.op_type.clone()).collect();
+        assert_eq!(
+            vec![
+                OperationType::HttpConnect,
+                OperationType::HttpTtfb,
+                OperationType::HttpTransfer
+            ],
+            types
+        );
+        // the phases follow each other without gaps
+        assert_eq!(ops[0].end, ops[1].start);
+        assert_eq!(ops[1].end, ops[2].start);
+        assert!(ops[1].duration() >= chrono::Duration::milliseconds(50));
+        assert!(ops[2].duration() >= chrono::Duration::milliseconds(50));
+        assert_eq!(
+            vec![0, 0, 4],
+            ops.iter().map(|op| op.size).collect::<Vec<_>>()
+        );
+        for op in &ops {
+            assert_eq!("foo/1", op.file);
+            assert!(op.error.is_empty());
+            assert!(op.warmup);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let server = StubServer::start(|request| {
+            let body = if request.query("delimiter").is_some() {
+                r#"{"objects":[{"key":"foo/1"}],"common_prefixes":["foo/bar/"],"next_key":"foo/2"}"#
+            } else {
+                r#"{"objects":[{"key":"foo/1"},{"key":"foo/2"}],"next_key":null}"#
+            };
+            Response::new(200, body)
+        })
+        .await;
+        let target = target(&server, None);
+        let bucket = bucket().await;
+
+        let (keys, next_key) = target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/1", "foo/2"], keys);
+        assert_eq!(None, next_key);
+
+        let page = target
+            .list_directory(&bucket, "foo/", "/", Some(b"foo/0".to_vec()), Some(1))
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/1"], page.keys);
+        assert_eq!(vec!["foo/bar/"], page.common_prefixes);
+        assert_eq!(Some(b"foo/2".to_vec()), page.next_key);
+
+        let requests = server.requests();
+        for request in &requests {
+            assert_eq!(format!("/v1/objects/{}", bucket.address()), request.path);
+            assert_eq!(Some("foo/"), request.query("prefix"));
+        }
+        assert_eq!(None, requests[0].query("delimiter"));
+        assert_eq!(None, requests[0].query("start_key"));
+        assert_eq!(None, requests[0].query("limit"));
+        assert_eq!(Some("/"), requests[1].query("delimiter"));
+        assert_eq!(Some("foo/0"), requests[1].query("start_key"));
+        assert_eq!(Some("1"), requests[1].query("limit"));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = StubServer::start(|request| match request.path.as_str() {
+            path if path.ends_with("/foo/1") => Response::new(404, "object not found"),
+            _ => Response::new(200, "not json"),
+        })
+        .await;
+        let collector = Arc::new(Collector::new());
+        let target = target(&server, Some(collector.clone()));
+        let bucket = bucket().await;
+
+        let err = target
+            .get_object(&bucket, "foo/1", Box::new(tokio::io::sink()), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("object not found"), "{err}");
+        let err = target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap_err();
+        assert_eq!("failed to parse list response", err.to_string());
+
+        // only the list request got a response to time
+        drop(target);
+        let mut collector = Arc::try_unwrap(collector).ok().unwrap();
+        collector.close().await;
+        assert_eq!(3, collector.get_operations().len());
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{Broadcast, MemoryConfig, MemoryOp};
+use crate::targets::sequencer::{SequenceError, SequencedWrites};
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_trait::async_trait;
+use rand::{thread_rng, Rng as _};
+use recall_provider::fvm_shared::address::Address;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use tokio::io::{AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::time::Instant;
+
+/// Keeps every bucket and object in memory. Meant for dry runs of a test config and for tests
+/// that need a [Target] without a live network.
+pub struct InMemoryTarget {
+    buckets: Mutex<HashMap<Address, BTreeMap<String, Vec<u8>>>>,
+    next_bucket_id: AtomicU64,
+    config: MemoryConfig,
+    /// When recently uploaded objects can be downloaded, if there is a resolve delay
+    resolvable_at: Mutex<HashMap<(Address, String), Instant>>,
+    next_tx_id: AtomicU64,
+    /// When transactions broadcast without waiting for the commit are included, if there
+    /// is an inclusion delay
+    included_at: Mutex<HashMap<String, Instant>>,
+    /// The sequence number the next sequenced write needs, the memory target acts as a
+    /// single account
+    sequence: Mutex<u64>,
+}
+
+impl Default for InMemoryTarget {
+    fn default() -> Self {
+        Self::new(MemoryConfig::default())
+    }
+}
+
+impl InMemoryTarget {
+    pub fn new(config: MemoryConfig) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            next_bucket_id: AtomicU64::new(1000),
+            config,
+            resolvable_at: Mutex::new(HashMap::new()),
+            next_tx_id: AtomicU64::new(0),
+            included_at: Mutex::new(HashMap::new()),
+            sequence: Mutex::new(0),
+        }
+    }
+
+    /// Sleeps for the configured latency and fails at the configured rate
+    async fn simulate(&self, op: &MemoryOp, name: &str) -> Result<()> {
+        if op.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(op.latency_ms)).await;
+        }
+        if op.failure_rate > 0.0 && thread_rng().gen_bool(op.failure_rate.min(1.0)) {
+            bail!("injected {name} failure");
+        }
+        Ok(())
+    }
+
+    fn with_bucket<T>(
+        &self,
+        bucket: &Bucket,
+        f: impl FnOnce(&mut BTreeMap<String, Vec<u8>>) -> Result<T>,
+    ) -> Result<T> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let objects = buckets
+            .get_mut(&bucket.address())
+            .ok_or_else(|| anyhow!("bucket {} not found", bucket.address()))?;
+        f(objects)
+    }
+
+    /// Applies a write like the chain does: if it has a `sequence`, only when it is the one
+    /// the account is at, which the write then uses up unless it fails
+    fn apply<T>(&self, sequence: Option<u64>, write: impl FnOnce() -> Result<T>) -> Result<T> {
+        let Some(sequence) = sequence else {
+            return write();
+        };
+        let mut expected = self.sequence.lock().unwrap();
+        if sequence != *expected {
+            return Err(SequenceError {
+                expected: *expected,
+                got: sequence,
+            }
+            .into());
+        }
+        let result = write()?;
+        *expected += 1;
+        Ok(result)
+    }
+
+    async fn add(
+        &self,
+        sequence: Option<u64>,
+        bucket: &Bucket,
+        key: &str,
+        mut payload: Payload,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        self.simulate(&self.config.put, "put").await?;
+        let mut data = Vec::with_capacity(payload.size as usize);
+        payload.reader.read_to_end(&mut data).await?;
+        self.apply(sequence, || {
+            self.with_bucket(bucket, |objects| {
+                if !overwrite && objects.contains_key(key) {
+                    bail!("object {key} already exists");
+                }
+                objects.insert(key.to_string(), data);
+                Ok(())
+            })
+        })?;
+        if self.config.resolve_delay_ms > 0 {
+            let resolvable_at =
+                Instant::now() + Duration::from_millis(self.config.resolve_delay_ms);
+            self.resolvable_at
+                .lock()
+                .unwrap()
+                .insert((bucket.address(), key.to_string()), resolvable_at);
+        }
+
+        let hash = format!("{:064X}", self.next_tx_id.fetch_add(1, Ordering::SeqCst));
+        if self.config.inclusion_delay_ms > 0 && !matches!(broadcast_mode, Broadcast::Commit) {
+            let included_at =
+                Instant::now() + Duration::from_millis(self.config.inclusion_delay_ms);
+            self.included_at
+                .lock()
+                .unwrap()
+                .insert(hash.clone(), included_at);
+        }
+        Ok(Some(hash))
+    }
+
+    async fn delete(&self, sequence: Option<u64>, bucket: &Bucket, key: &str) -> Result<()> {
+        self.simulate(&self.config.delete, "delete").await?;
+        self.apply(sequence, || {
+            self.with_bucket(bucket, |objects| {
+                objects
+                    .remove(key)
+                    .map(|_| ())
+                    .ok_or_else(|| anyhow!("object {key} not found"))
+            })
+        })
+    }
+}
+
+/// Parses an inclusive `start-end`, `start-` or `-suffix` range into slice bounds
+fn parse_range(range: &str, len: usize) -> Result<(usize, usize)> {
+    let range = range.trim_start_matches("bytes=");
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| anyhow!("invalid range {range}"))?;
+    let (start, end) = match (start, end) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse()?;
+            (len.saturating_sub(suffix), len)
+        }
+        (start, "") => (start.parse()?, len),
+        (start, end) => (
+            start.parse()?,
+            end.parse::<usize>()?.saturating_add(1).min(len),
+        ),
+    };
+    if start >= end {
+        bail!("range {range} is not satisfiable for {len} bytes");
+    }
+    Ok((start, end))
+}
+
+#[async_trait]
+impl Target for InMemoryTarget {
+    async fn create_bucket(&self) -> Result<Bucket> {
+        let id = self.next_bucket_id.fetch_add(1, Ordering::Relaxed);
+        let address = Address::new_id(id);
+        self.buckets
+            .lock()
+            .unwrap()
+            .insert(address, BTreeMap::new());
+        Bucket::attach(address).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        self.simulate(&self.config.list, "list").await?;
+        let start = start_key
+            .map(String::from_utf8)
+            .transpose()
+            .context("invalid start key")?
+            .unwrap_or_else(|| prefix.to_string());
+
+        let page_size = limit.map_or(self.config.page_size, |limit| limit as usize);
+        self.with_bucket(bucket, |objects| {
+            let mut keys = objects
+                .range(start..)
+                .map(|(key, _)| key)
+                .take_while(|key| key.starts_with(prefix));
+            let page: Vec<String> = keys.by_ref().take(page_size.max(1)).cloned().collect();
+            let next_key = keys.next().map(|key| key.clone().into_bytes());
+            Ok((page, next_key))
+        })
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        self.simulate(&self.config.list, "list").await?;
+        let start = start_key
+            .map(String::from_utf8)
+            .transpose()
+            .context("invalid start key")?
+            .unwrap_or_else(|| prefix.to_string());
+        let page_size = limit.map_or(self.config.page_size, |limit| limit as usize);
+
+        self.with_bucket(bucket, |objects| {
+            let mut page = ListPage::default();
+            let keys = objects
+                .range(start..)
+                .map(|(key, _)| key)
+                .take_while(|key| key.starts_with(prefix));
+            for key in keys {
+                let common_prefix = key[prefix.len()..]
+                    .find(delimiter)
+                    .filter(|_| !delimiter.is_empty())
+                    .map(|at| &key[..prefix.len() + at + delimiter.len()]);
+                // keys of a common prefix follow each other, only the first one counts
+                let last = page.common_prefixes.last();
+                if common_prefix.is_some_and(|common| last.is_some_and(|last| last == common)) {
+                    continue;
+                }
+                if page.keys.len() + page.common_prefixes.len() == page_size.max(1) {
+                    page.next_key = Some(key.clone().into_bytes());
+                    break;
+                }
+                match common_prefix {
+                    Some(common_prefix) => page.common_prefixes.push(common_prefix.to_string()),
+                    None => page.keys.push(key.clone()),
+                }
+            }
+            Ok(page)
+        })
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        _metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        self.add(None, bucket, key, payload, overwrite, broadcast_mode)
+            .await
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        mut writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        self.simulate(&self.config.get, "get").await?;
+        let resolvable_at = self
+            .resolvable_at
+            .lock()
+            .unwrap()
+            .get(&(bucket.address(), key.to_string()))
+            .copied();
+        if resolvable_at.is_some_and(|at| Instant::now() < at) {
+            bail!("object {key} not found");
+        }
+        let mut data = self.with_bucket(bucket, |objects| {
+            objects
+                .get(key)
+                .cloned()
+                .ok_or_else(|| anyhow!("object {key} not found"))
+        })?;
+        let (start, end) = match range {
+            Some(range) => parse_range(&range, data.len())?,
+            None => (0, data.len()),
+        };
+        let corrupt = self.config.corruption_rate > 0.0
+            && thread_rng().gen_bool(self.config.corruption_rate.min(1.0));
+        if corrupt && start < end {
+            data[start] ^= 0xff;
+        }
+        writer.write_all(&data[start..end]).await?;
+        writer.shutdown().await?;
+        Ok(())
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        self.delete(None, bucket, key).await
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        let included_at = self.included_at.lock().unwrap().get(hash).copied();
+        if included_at.is_some_and(|at| Instant::now() < at) {
+            return Ok(Inclusion::Pending);
+        }
+        Ok(Inclusion::Included)
+    }
+
+    fn sequenced(&self) -> Option<&dyn SequencedWrites> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl SequencedWrites for InMemoryTarget {
+    async fn sequence(&self) -> Result<u64> {
+        Ok(*self.sequence.lock().unwrap())
+    }
+
+    async fn add_object_at(
+        &self,
+        sequence: u64,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        _metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        self.add(
+            Some(sequence),
+            bucket,
+            key,
+            payload,
+            overwrite,
+            broadcast_mode,
+        )
+        .await
+    }
+
+    async fn delete_object_at(&self, sequence: u64, bucket: &Bucket, key: &str) -> Result<()> {
+        self.delete(Some(sequence), bucket, key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::list_bucket_items;
+    use std::sync::Arc;
+
+    async fn add(
+        target: &InMemoryTarget,
+        bucket: &Bucket,
+        key: &str,
+        data: &[u8],
+        overwrite: bool,
+    ) -> Result<()> {
+        let payload = Payload::new(std::io::Cursor::new(data.to_vec()), data.len() as u64);
+        target
+            .add_object(
+                bucket,
+                key,
+                payload,
+                HashMap::new(),
+                overwrite,
+                Broadcast::Commit,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_and_range() {
+        let target = InMemoryTarget::default();
+        let bucket = target.create_bucket().await.unwrap();
+
+        add(&target, &bucket, "foo/1", b"hello world", false)
+            .await
+            .unwrap();
+        assert!(add(&target, &bucket, "foo/1", b"other", false)
+            .await
+            .is_err());
+        add(&target, &bucket, "foo/1", b"0123456789", true)
+            .await
+            .unwrap();
+
+        let data = target
+            .with_bucket(&bucket, |objects| Ok(objects["foo/1"].clone()))
+            .unwrap();
+        assert_eq!(b"0123456789".to_vec(), data);
+        assert_eq!((2, 5), parse_range("2-4", data.len()).unwrap());
+        assert_eq!((7, 10), parse_range("-3", data.len()).unwrap());
+        assert_eq!((8, 10), parse_range("bytes=8-", data.len()).unwrap());
+        assert!(parse_range("12-", data.len()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_paginates_with_next_key() {
+        let target = Arc::new(InMemoryTarget::new(MemoryConfig {
+            page_size: 3,
+            ..Default::default()
+        }));
+        let bucket = target.create_bucket().await.unwrap();
+        for i in 0..10 {
+            add(&target, &bucket, &format!("foo/{i}"), b"x", true)
+                .await
+                .unwrap();
+        }
+        add(&target, &bucket, "other/1", b"x", true).await.unwrap();
+
+        let (first, next_key) = target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/0", "foo/1", "foo/2"], first);
+        assert_eq!(Some(b"foo/3".to_vec()), next_key);
+
+        let (keys, durations) = list_bucket_items(target.clone(), &bucket, "foo/")
+            .await
+            .unwrap();
+        assert_eq!(10, keys.len());
+        assert_eq!(4, durations.len());
+    }
+
+    #[tokio::test]
+    async fn test_list_directory() {
+        let target = InMemoryTarget::default();
+        let bucket = target.create_bucket().await.unwrap();
+        let keys = [
+            "foo/a/1",
+            "foo/a/2",
+            "foo/b/1",
+            "foo/c",
+            "foo/d/e/1",
+            "other/1",
+        ];
+        for key in keys {
+            add(&target, &bucket, key, b"x", true).await.unwrap();
+        }
+
+        let page = target
+            .list_directory(&bucket, "foo/", "/", None, Some(2))
+            .await
+            .unwrap();
+        assert!(page.keys.is_empty());
+        assert_eq!(vec!["foo/a/", "foo/b/"], page.common_prefixes);
+        assert_eq!(Some(b"foo/c".to_vec()), page.next_key);
+
+        let page = target
+            .list_directory(&bucket, "foo/", "/", page.next_key, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/c"], page.keys);
+        assert_eq!(vec!["foo/d/"], page.common_prefixes);
+        assert_eq!(None, page.next_key);
+
+        let page = target
+            .list_directory(&bucket, "foo/a/", "/", None, None)
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/a/1", "foo/a/2"], page.keys);
+        assert!(page.common_prefixes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate() {
+        let target = InMemoryTarget::new(MemoryConfig {
+            delete: MemoryOp {
+                failure_rate: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let bucket = target.create_bucket().await.unwrap();
+        add(&target, &bucket, "foo/1", b"x", true).await.unwrap();
+
+        let err = target.delete_object(&bucket, "foo/1").await.unwrap_err();
+        assert_eq!("injected delete failure", err.to_string());
+    }
+}
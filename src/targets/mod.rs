@@ -1,32 +1,91 @@
 use std::collections::HashMap;
-use std::path::Path;
 
 use crate::config::Broadcast;
 use anyhow::Result;
 use async_trait::async_trait;
 use recall_sdk::machine::bucket::Bucket;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncWrite};
 
+pub mod chaos;
+pub mod http;
+pub mod memory;
+pub mod recorder;
+pub mod s3;
 pub mod sdk;
+pub mod sequencer;
+#[cfg(test)]
+pub(crate) mod stub_server;
+
+/// Streaming source of an upload. `Sync` is required by bodies that are sent over HTTP
+pub struct Payload {
+    pub reader: Box<dyn AsyncRead + Unpin + Send + Sync + 'static>,
+    pub size: u64,
+}
+
+/// Where a broadcast transaction is at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inclusion {
+    /// Not in a block yet
+    Pending,
+    Included,
+    /// In a block, but it failed to execute
+    Failed(String),
+}
+
+/// One page of a directory listing
+#[derive(Debug, Default)]
+pub struct ListPage {
+    pub keys: Vec<String>,
+    /// Prefixes that group the keys below the listed directory, each ending in the delimiter
+    pub common_prefixes: Vec<String>,
+    /// Where the next page starts, if there is one
+    pub next_key: Option<Vec<u8>>,
+}
+
+impl Payload {
+    pub fn new(reader: impl AsyncRead + Unpin + Send + Sync + 'static, size: u64) -> Self {
+        Self {
+            reader: Box::new(reader),
+            size,
+        }
+    }
+}
 
 #[async_trait]
 pub trait Target: Send + Sync {
     async fn create_bucket(&self) -> Result<Bucket>;
+    /// Lists one page of the keys under `prefix`, starting at `start_key`. Returns the key to
+    /// start the next page at, if there is one. `limit` caps the page size, the target's
+    /// default applies if it isn't set
     async fn list_objects(
         &self,
         bucket: &Bucket,
         prefix: &str,
         start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
     ) -> Result<(Vec<String>, Option<Vec<u8>>)>;
+    /// Lists one page of what is directly under `prefix`, like a directory. Keys that
+    /// contain `delimiter` after the prefix are returned as their common prefix, up to and
+    /// including the delimiter
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage>;
+    /// Returns the hash of the transaction that was broadcast, for targets that write
+    /// through transactions
     async fn add_object(
         &self,
         bucket: &Bucket,
         key: &str,
-        path: &Path,
+        payload: Payload,
         metadata: HashMap<String, String>,
         overwrite: bool,
         broadcast_mode: Broadcast,
-    ) -> Result<()>;
+    ) -> Result<Option<String>>;
 
     async fn get_object(
         &self,
@@ -36,4 +95,23 @@ pub trait Target: Send + Sync {
         range: Option<String>,
     ) -> Result<()>;
     async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()>;
+
+    /// Called after a failed write so the target can recover, e.g. by resyncing the wallet
+    /// sequence that was incremented for a transaction that never landed
+    async fn resync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Where the transaction with `hash`, returned by a write of this target, is at.
+    /// Targets without transactions apply every write right away
+    async fn inclusion(&self, _hash: &str) -> Result<Inclusion> {
+        Ok(Inclusion::Included)
+    }
+
+    /// Writes that take the sequence number of their transaction, for targets that sign
+    /// with an account. [SequencedTarget](sequencer::SequencedTarget) uses them to keep
+    /// several writes of one account in flight
+    fn sequenced(&self) -> Option<&dyn sequencer::SequencedWrites> {
+        None
+    }
 }
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::Broadcast;
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::Result;
+use async_trait::async_trait;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use tokio::io::{AsyncWrite, AsyncWriteExt as _, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceOp {
+    CreateBucket,
+    Put,
+    Get,
+    List,
+    Delete,
+}
+
+/// One line of a trace file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// Milliseconds since the recording started
+    pub offset_ms: u64,
+    /// The runner (thread id) that issued the call
+    pub account: String,
+    pub op: TraceOp,
+    /// Bucket address. For `createBucket` this is the address of the new bucket
+    pub bucket: String,
+    /// Object key, or the prefix for `list`
+    #[serde(default)]
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_key: Option<String>,
+    /// Page size of a `list`, missing if the target's default was used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    /// Delimiter of a directory `list`, missing for flat listings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+}
+
+// TraceRecorder writes trace entries as JSON lines in the background
+pub struct TraceRecorder {
+    start: Instant,
+    sender: Option<mpsc::Sender<TraceEntry>>,
+    background_task: Option<JoinHandle<Result<()>>>,
+}
+
+impl TraceRecorder {
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        let (sender, mut receiver) = mpsc::channel::<TraceEntry>(100);
+        let background_task = tokio::spawn(async move {
+            let mut writer = BufWriter::new(file);
+            while let Some(entry) = receiver.recv().await {
+                let mut line = serde_json::to_vec(&entry)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+            }
+            writer.flush().await?;
+            Ok(())
+        });
+
+        Ok(Self {
+            start: Instant::now(),
+            sender: Some(sender),
+            background_task: Some(background_task),
+        })
+    }
+
+    fn offset_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    async fn record(&self, entry: TraceEntry) {
+        if let Some(sender) = &self.sender {
+            if sender.send(entry).await.is_err() {
+                error!("trace writer stopped, dropping entry");
+            }
+        }
+    }
+
+    /// Closes the recorder and waits until every entry is written
+    pub async fn close(&mut self) -> Result<()> {
+        self.sender.take();
+        if let Some(task) = self.background_task.take() {
+            task.await??;
+        }
+        Ok(())
+    }
+}
+
+/// Records every call to the wrapped [Target] in a trace that `replay` can play back
+pub struct RecordingTarget {
+    inner: Arc<dyn Target>,
+    recorder: Arc<TraceRecorder>,
+    account: String,
+}
+
+impl RecordingTarget {
+    /// Wraps `target` if a recorder is given, otherwise returns it as is
+    pub fn wrap(
+        target: Arc<dyn Target>,
+        recorder: &Option<Arc<TraceRecorder>>,
+        account: String,
+    ) -> Arc<dyn Target> {
+        match recorder {
+            Some(recorder) => Arc::new(Self {
+                inner: target,
+                recorder: recorder.clone(),
+                account,
+            }),
+            None => target,
+        }
+    }
+
+    fn entry(&self, op: TraceOp, bucket: &Bucket, key: &str) -> TraceEntry {
+        TraceEntry {
+            offset_ms: self.recorder.offset_ms(),
+            account: self.account.clone(),
+            op,
+            bucket: bucket.address().to_string(),
+            key: key.to_string(),
+            size: None,
+            range: None,
+            start_key: None,
+            limit: None,
+            delimiter: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Target for RecordingTarget {
+    async fn create_bucket(&self) -> Result<Bucket> {
+        let offset_ms = self.recorder.offset_ms();
+        let bucket = self.inner.create_bucket().await?;
+        self.recorder
+            .record(TraceEntry {
+                offset_ms,
+                ..self.entry(TraceOp::CreateBucket, &bucket, "")
+            })
+            .await;
+        Ok(bucket)
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        let entry = TraceEntry {
+            start_key: start_key
+                .as_ref()
+                .map(|key| String::from_utf8_lossy(key).to_string()),
+            limit,
+            ..self.entry(TraceOp::List, bucket, prefix)
+        };
+        self.recorder.record(entry).await;
+        self.inner
+            .list_objects(bucket, prefix, start_key, limit)
+            .await
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        let entry = TraceEntry {
+            start_key: start_key
+                .as_ref()
+                .map(|key| String::from_utf8_lossy(key).to_string()),
+            limit,
+            delimiter: Some(delimiter.to_string()),
+            ..self.entry(TraceOp::List, bucket, prefix)
+        };
+        self.recorder.record(entry).await;
+        self.inner
+            .list_directory(bucket, prefix, delimiter, start_key, limit)
+            .await
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        let entry = TraceEntry {
+            size: Some(payload.size),
+            ..self.entry(TraceOp::Put, bucket, key)
+        };
+        self.recorder.record(entry).await;
+        self.inner
+            .add_object(bucket, key, payload, metadata, overwrite, broadcast_mode)
+            .await
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        let entry = TraceEntry {
+            range: range.clone(),
+            ..self.entry(TraceOp::Get, bucket, key)
+        };
+        self.recorder.record(entry).await;
+        self.inner.get_object(bucket, key, writer, range).await
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        self.recorder
+            .record(self.entry(TraceOp::Delete, bucket, key))
+            .await;
+        self.inner.delete_object(bucket, key).await
+    }
+
+    async fn resync(&self) -> Result<()> {
+        self.inner.resync().await
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        self.inner.inclusion(hash).await
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use crate::config::{Broadcast, S3Config};
+use crate::targets::{ListPage, Payload, Target};
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use futures::TryStreamExt as _;
+use http_body_util::StreamBody;
+use hyper::body::Frame;
+use rand::{thread_rng, Rng as _};
+use recall_provider::util::parse_address;
+use recall_sdk::machine::bucket::Bucket;
+use recall_sdk::machine::Machine;
+use tokio::io::AsyncWrite;
+use tokio_util::io::ReaderStream;
+
+/// Size of the chunks an upload is read and sent in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Talks to Recall's S3-compatible gateway instead of the chain directly.
+/// Buckets are addressed by their machine address, so any S3 bucket used
+/// here must be named after the address string (e.g. `t2abc...`).
+pub struct S3Target {
+    client: Client,
+}
+
+impl S3Target {
+    pub fn new(config: &S3Config) -> Result<Self> {
+        let endpoint = config
+            .endpoint
+            .clone()
+            .ok_or_else(|| anyhow!("the s3 target requires an endpoint"))?;
+        let credentials = Credentials::new(
+            config.access_key_id.clone().unwrap_or_default(),
+            config.secret_access_key.clone().unwrap_or_default(),
+            None,
+            None,
+            "recall-loader",
+        );
+        let s3_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .region(Region::new(config.region.clone()))
+            .credentials_provider(credentials)
+            .force_path_style(true)
+            .build();
+
+        Ok(Self {
+            client: Client::from_conf(s3_config),
+        })
+    }
+}
+
+fn bucket_name(bucket: &Bucket) -> String {
+    bucket.address().to_string()
+}
+
+/// Metadata is sent as `x-amz-meta-<key>` headers, so keys must be valid header names
+fn check_metadata_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !valid {
+        bail!("metadata key {key:?} can't be sent as an S3 header");
+    }
+    Ok(())
+}
+
+/// The SDK takes ranges as `start-end`, S3 wants a full `Range` header value.
+fn range_header(range: String) -> String {
+    if range.starts_with("bytes=") {
+        range
+    } else {
+        format!("bytes={range}")
+    }
+}
+
+#[async_trait]
+impl Target for S3Target {
+    async fn create_bucket(&self) -> Result<Bucket> {
+        let name = format!("recall-loader-{}", thread_rng().gen::<u32>());
+        let output = self
+            .client
+            .create_bucket()
+            .bucket(&name)
+            .send()
+            .await
+            .with_context(|| format!("failed to create bucket {name}"))?;
+
+        // the gateway creates a new machine and reports its address as the location, e.g.
+        // `/t2abc...`
+        let location = output
+            .location()
+            .ok_or_else(|| anyhow!("gateway returned no location for bucket {name}"))?;
+        let address = parse_address(location.trim_start_matches('/')).with_context(|| {
+            format!("gateway returned location {location:?} for bucket {name}, not /<address>")
+        })?;
+        Bucket::attach(address).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        let continuation_token = start_key
+            .map(String::from_utf8)
+            .transpose()
+            .context("invalid continuation token")?;
+        let output = self
+            .client
+            .list_objects_v2()
+            .bucket(bucket_name(bucket))
+            .prefix(prefix)
+            .set_continuation_token(continuation_token)
+            .set_max_keys(limit.map(|limit| limit.min(i32::MAX as u64) as i32))
+            .send()
+            .await?;
+
+        let results = output
+            .contents()
+            .iter()
+            .filter_map(|object| object.key().map(str::to_string))
+            .collect();
+        let next_key = output
+            .next_continuation_token()
+            .map(|token| token.as_bytes().to_vec());
+
+        Ok((results, next_key))
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        let continuation_token = start_key
+            .map(String::from_utf8)
+            .transpose()
+            .context("invalid continuation token")?;
+        let output = self
+            .client
+            .list_objects_v2()
+            .bucket(bucket_name(bucket))
+            .prefix(prefix)
+            .delimiter(delimiter)
+            .set_continuation_token(continuation_token)
+            .set_max_keys(limit.map(|limit| limit.min(i32::MAX as u64) as i32))
+            .send()
+            .await?;
+
+        Ok(ListPage {
+            keys: output
+                .contents()
+                .iter()
+                .filter_map(|object| object.key().map(str::to_string))
+                .collect(),
+            common_prefixes: output
+                .common_prefixes()
+                .iter()
+                .filter_map(|prefix| prefix.prefix().map(str::to_string))
+                .collect(),
+            next_key: output
+                .next_continuation_token()
+                .map(|token| token.as_bytes().to_vec()),
+        })
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        // the gateway owns the transactions, so there is nothing to broadcast from here
+        _broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        for key in metadata.keys() {
+            check_metadata_key(key)?;
+        }
+        let stream = ReaderStream::with_capacity(payload.reader, STREAM_CHUNK_SIZE);
+        let body = ByteStream::from_body_1_x(StreamBody::new(stream.map_ok(Frame::data)));
+        let mut request = self
+            .client
+            .put_object()
+            .bucket(bucket_name(bucket))
+            .key(key)
+            .body(body)
+            .content_length(payload.size as i64)
+            .set_metadata(Some(metadata));
+        if !overwrite {
+            request = request.if_none_match("*");
+        }
+        request.send().await?;
+
+        Ok(None)
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        mut writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(bucket_name(bucket))
+            .key(key)
+            .set_range(range.map(range_header))
+            .send()
+            .await?;
+
+        let mut body = output.body.into_async_read();
+        tokio::io::copy(&mut body, &mut writer).await?;
+        Ok(())
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(bucket_name(bucket))
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::stub_server::{Response, StubServer};
+    use recall_provider::fvm_shared::address::Address;
+
+    const LIST_PAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <IsTruncated>true</IsTruncated>
+  <Contents><Key>foo/1</Key></Contents>
+  <Contents><Key>foo/2</Key></Contents>
+  <CommonPrefixes><Prefix>foo/bar/</Prefix></CommonPrefixes>
+  <NextContinuationToken>token-2</NextContinuationToken>
+</ListBucketResult>"#;
+
+    fn target(server: &StubServer) -> S3Target {
+        S3Target::new(&S3Config {
+            endpoint: Some(server.url()),
+            access_key_id: Some("test".to_string()),
+            secret_access_key: Some("test".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    async fn bucket() -> Bucket {
+        Bucket::attach(Address::new_id(1001)).await.unwrap()
+    }
+
+    fn error(status: u16, code: &str) -> Response {
+        let body = format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>");
+        Response::new(status, body).header("content-type", "application/xml")
+    }
+
+    #[test]
+    fn test_requires_an_endpoint() {
+        assert!(S3Target::new(&S3Config::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_maps_continuation_tokens() {
+        let server = StubServer::start(|_| Response::new(200, LIST_PAGE)).await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        let (keys, next_key) = target
+            .list_objects(&bucket, "foo/", None, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/1", "foo/2"], keys);
+        assert_eq!(Some(b"token-2".to_vec()), next_key);
+
+        // the next key is passed back as the continuation token, limits beyond what S3
+        // takes are clamped
+        target
+            .list_objects(&bucket, "foo/", next_key, Some(u64::MAX))
+            .await
+            .unwrap();
+        target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(3, requests.len());
+        for request in &requests {
+            assert_eq!("GET", request.method);
+            assert_eq!(format!("/{}/", bucket_name(&bucket)), request.path);
+            assert_eq!(Some("2"), request.query("list-type"));
+            assert_eq!(Some("foo/"), request.query("prefix"));
+            assert_eq!(None, request.query("delimiter"));
+        }
+        assert_eq!(None, requests[0].query("continuation-token"));
+        assert_eq!(Some("2"), requests[0].query("max-keys"));
+        assert_eq!(Some("token-2"), requests[1].query("continuation-token"));
+        assert_eq!(Some("2147483647"), requests[1].query("max-keys"));
+        assert_eq!(None, requests[2].query("max-keys"));
+    }
+
+    #[tokio::test]
+    async fn test_list_directory() {
+        let server = StubServer::start(|_| Response::new(200, LIST_PAGE)).await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        let page = target
+            .list_directory(&bucket, "foo/", "/", Some(b"token-1".to_vec()), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(vec!["foo/1", "foo/2"], page.keys);
+        assert_eq!(vec!["foo/bar/"], page.common_prefixes);
+        assert_eq!(Some(b"token-2".to_vec()), page.next_key);
+
+        let request = &server.requests()[0];
+        assert_eq!(Some("/"), request.query("delimiter"));
+        assert_eq!(Some("token-1"), request.query("continuation-token"));
+        assert_eq!(Some("2"), request.query("max-keys"));
+
+        // a start key that can't be a continuation token never reaches the gateway
+        let result = target
+            .list_directory(&bucket, "foo/", "/", Some(vec![0xff, 0xfe]), None)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(1, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn test_add_object_sends_metadata() {
+        let server = StubServer::start(|_| Response::new(200, "")).await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        for overwrite in [true, false] {
+            let result = target
+                .add_object(
+                    &bucket,
+                    "foo/1",
+                    Payload::new(std::io::Cursor::new(vec![7; 1000]), 1000),
+                    HashMap::from([("origin".to_string(), "loader".to_string())]),
+                    overwrite,
+                    Broadcast::Commit,
+                )
+                .await
+                .unwrap();
+            assert_eq!(None, result);
+        }
+
+        let requests = server.requests();
+        assert_eq!(2, requests.len());
+        for request in &requests {
+            assert_eq!("PUT", request.method);
+            assert_eq!(format!("/{}/foo/1", bucket_name(&bucket)), request.path);
+            assert_eq!(Some("loader"), request.header("x-amz-meta-origin"));
+            assert_eq!(vec![7; 1000], request.body);
+        }
+        assert_eq!(None, requests[0].header("if-none-match"));
+        assert_eq!(Some("*"), requests[1].header("if-none-match"));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_metadata_keys_that_are_no_header_names() {
+        let server = StubServer::start(|_| Response::new(200, "")).await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        for key in ["upload bench test", "", "caf\u{e9}"] {
+            let err = target
+                .add_object(
+                    &bucket,
+                    "foo/1",
+                    Payload::new(std::io::Cursor::new(vec![7; 10]), 10),
+                    HashMap::from([(key.to_string(), "foo/1".to_string())]),
+                    true,
+                    Broadcast::Commit,
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(
+                format!("metadata key {key:?} can't be sent as an S3 header"),
+                err.to_string()
+            );
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_bucket_from_location() {
+        let server =
+            StubServer::start(|_| Response::new(200, "").header("location", "/t01001")).await;
+        let bucket = target(&server).create_bucket().await.unwrap();
+        assert_eq!(Address::new_id(1001), bucket.address());
+
+        let request = &server.requests()[0];
+        assert_eq!("PUT", request.method);
+        assert!(request.path.starts_with("/recall-loader-"));
+    }
+
+    #[tokio::test]
+    async fn test_create_bucket_needs_an_address() {
+        let server = StubServer::start(|_| Response::new(200, "")).await;
+        let err = target(&server).create_bucket().await.unwrap_err();
+        let name = server.requests()[0].path.trim_matches('/').to_string();
+        assert_eq!(
+            format!("gateway returned no location for bucket {name}"),
+            err.to_string()
+        );
+
+        let server = StubServer::start(|_| Response::new(200, "").header("location", "/foo")).await;
+        let err = target(&server).create_bucket().await.unwrap_err();
+        let name = server.requests()[0].path.trim_matches('/').to_string();
+        assert_eq!(
+            format!("gateway returned location \"/foo\" for bucket {name}, not /<address>"),
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_object_range() {
+        let server = StubServer::start(|_| Response::new(206, "abcd")).await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        let (writer, mut reader) = tokio::io::duplex(64);
+        target
+            .get_object(&bucket, "foo/1", Box::new(writer), Some("0-3".to_string()))
+            .await
+            .unwrap();
+        let mut body = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut body)
+            .await
+            .unwrap();
+        assert_eq!("abcd", body);
+
+        let request = &server.requests()[0];
+        assert_eq!(format!("/{}/foo/1", bucket_name(&bucket)), request.path);
+        assert_eq!(Some("bytes=0-3"), request.header("range"));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = StubServer::start(|request| match request.method.as_str() {
+            "PUT" => error(412, "PreconditionFailed"),
+            "DELETE" => error(403, "AccessDenied"),
+            _ => error(404, "NoSuchKey"),
+        })
+        .await;
+        let target = target(&server);
+        let bucket = bucket().await;
+
+        let result = target
+            .add_object(
+                &bucket,
+                "foo/1",
+                Payload::new(std::io::Cursor::new(vec![7; 10]), 10),
+                HashMap::new(),
+                false,
+                Broadcast::Commit,
+            )
+            .await;
+        assert!(result.is_err());
+        let result = target
+            .get_object(&bucket, "foo/1", Box::new(tokio::io::sink()), None)
+            .await;
+        assert!(result.is_err());
+        assert!(target.delete_object(&bucket, "foo/1").await.is_err());
+        assert!(target
+            .list_objects(&bucket, "foo/", None, None)
+            .await
+            .is_err());
+        // client errors aren't retried
+        assert_eq!(4, server.requests().len());
+    }
+}
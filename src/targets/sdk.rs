@@ -1,19 +1,92 @@
 use std::collections::HashMap;
-use std::path::Path;
 
 use crate::config::Broadcast;
-use crate::targets::Target;
-use anyhow::Result;
+use crate::targets::http::HttpClient;
+use crate::targets::sequencer::SequencedWrites;
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::{anyhow, Context as _, Result};
 use async_trait::async_trait;
 use recall_provider::json_rpc::JsonRpcProvider;
 use recall_sdk::machine::bucket::{AddOptions, Bucket, DeleteOptions, GetOptions, QueryOptions};
 use recall_sdk::machine::Machine;
-use recall_signer::Wallet;
+use recall_signer::key::SecretKey;
+use recall_signer::{AccountKind, SubnetID, Wallet};
 use tokio::io::AsyncWrite;
+use url::Url;
 
 pub struct SdkTarget {
     pub provider: JsonRpcProvider,
     pub wallet: Wallet,
+    /// Key of the wallet, sequenced writes sign with a wallet of their own
+    pub sk: SecretKey,
+    pub subnet_id: SubnetID,
+    /// CometBFT RPC of the network, polled for the inclusion of broadcast transactions
+    pub rpc_url: Url,
+    /// Client of the inclusion polls
+    pub(crate) client: HttpClient,
+}
+
+/// Body of CometBFT's `GET /tx?hash=..`. The result is missing until the transaction is in
+/// a block
+#[derive(serde::Deserialize)]
+struct TxResponse {
+    result: Option<TxResult>,
+}
+
+#[derive(serde::Deserialize)]
+struct TxResult {
+    tx_result: ExecTxResult,
+}
+
+#[derive(serde::Deserialize)]
+struct ExecTxResult {
+    #[serde(default)]
+    code: u32,
+    #[serde(default)]
+    log: String,
+}
+
+impl SdkTarget {
+    /// A wallet of the account that doesn't share its sequence with `wallet`
+    fn own_wallet(&self) -> Result<Wallet> {
+        Wallet::new_secp256k1(
+            self.sk.clone(),
+            AccountKind::Ethereum,
+            self.subnet_id.clone(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn add(
+        &self,
+        wallet: &mut Wallet,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        let opts = AddOptions {
+            metadata,
+            overwrite,
+            broadcast_mode: broadcast_mode.into(),
+            ..Default::default()
+        };
+        let tx = bucket
+            .add_reader(&self.provider, wallet, key, payload.reader, opts)
+            .await?;
+
+        Ok(Some(tx.hash().to_string()))
+    }
+
+    async fn delete(&self, wallet: &mut Wallet, bucket: &Bucket, key: &str) -> Result<()> {
+        let opts = DeleteOptions {
+            ..Default::default()
+        };
+        bucket.delete(&self.provider, wallet, key, opts).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -36,12 +109,16 @@ impl Target for SdkTarget {
         bucket: &Bucket,
         prefix: &str,
         start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
     ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
-        let options = QueryOptions {
+        let mut options = QueryOptions {
             prefix: prefix.to_string(),
             start_key,
             ..Default::default()
         };
+        if let Some(limit) = limit {
+            options.limit = limit;
+        }
 
         let result = bucket.query(&self.provider, options).await?;
         let mut results = Vec::new();
@@ -54,27 +131,53 @@ impl Target for SdkTarget {
         Ok((results, result.next_key.clone()))
     }
 
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        let mut options = QueryOptions {
+            prefix: prefix.to_string(),
+            delimiter: delimiter.to_string(),
+            start_key,
+            ..Default::default()
+        };
+        if let Some(limit) = limit {
+            options.limit = limit;
+        }
+
+        let result = bucket.query(&self.provider, options).await?;
+        let utf8 = |bytes: &[u8]| String::from_utf8_lossy(bytes).to_string();
+        Ok(ListPage {
+            keys: result.objects.iter().map(|(key, _)| utf8(key)).collect(),
+            common_prefixes: result.common_prefixes.iter().map(|p| utf8(p)).collect(),
+            next_key: result.next_key,
+        })
+    }
+
     async fn add_object(
         &self,
         bucket: &Bucket,
         key: &str,
-        path: &Path,
+        payload: Payload,
         metadata: HashMap<String, String>,
         overwrite: bool,
         broadcast_mode: Broadcast,
-    ) -> Result<()> {
+    ) -> Result<Option<String>> {
         let mut wallet = self.wallet.clone();
-        let opts = AddOptions {
-            metadata: metadata.clone(),
+        self.add(
+            &mut wallet,
+            bucket,
+            key,
+            payload,
+            metadata,
             overwrite,
-            broadcast_mode: broadcast_mode.into(),
-            ..Default::default()
-        };
-        let _ = bucket
-            .add_from_path(&self.provider, &mut wallet, key, path, opts)
-            .await?;
-
-        Ok(())
+            broadcast_mode,
+        )
+        .await
     }
 
     async fn get_object(
@@ -93,12 +196,78 @@ impl Target for SdkTarget {
 
     async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
         let mut wallet = self.wallet.clone();
-        let opts = DeleteOptions {
-            ..Default::default()
-        };
-        bucket
-            .delete(&self.provider, &mut wallet, key, opts)
-            .await?;
-        Ok(())
+        self.delete(&mut wallet, bucket, key).await
+    }
+
+    async fn resync(&self) -> Result<()> {
+        // the wallet shares its sequence with its clones, so this resets it for all of them
+        // TODO: update SDK to be nicer here
+        self.wallet.clone().init_sequence(&self.provider).await
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        let mut url = self.rpc_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("invalid rpc url {}", self.rpc_url))?
+            .pop_if_empty()
+            .push("tx");
+        url.query_pairs_mut()
+            .append_pair("hash", &format!("0x{hash}"));
+
+        let mut body = Vec::new();
+        self.client.get(&url, None, &mut body).await?;
+        let response: TxResponse =
+            serde_json::from_slice(&body).context("failed to parse tx response")?;
+        Ok(match response.result {
+            None => Inclusion::Pending,
+            Some(result) if result.tx_result.code == 0 => Inclusion::Included,
+            Some(result) => Inclusion::Failed(format!(
+                "transaction failed with code {}: {}",
+                result.tx_result.code, result.tx_result.log
+            )),
+        })
+    }
+
+    fn sequenced(&self) -> Option<&dyn SequencedWrites> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl SequencedWrites for SdkTarget {
+    async fn sequence(&self) -> Result<u64> {
+        let mut wallet = self.own_wallet()?;
+        wallet.init_sequence(&self.provider).await?;
+        Ok(wallet.sequence())
+    }
+
+    async fn add_object_at(
+        &self,
+        sequence: u64,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        let mut wallet = self.own_wallet()?;
+        wallet.set_sequence(sequence);
+        self.add(
+            &mut wallet,
+            bucket,
+            key,
+            payload,
+            metadata,
+            overwrite,
+            broadcast_mode,
+        )
+        .await
+    }
+
+    async fn delete_object_at(&self, sequence: u64, bucket: &Bucket, key: &str) -> Result<()> {
+        let mut wallet = self.own_wallet()?;
+        wallet.set_sequence(sequence);
+        self.delete(&mut wallet, bucket, key).await
     }
 }
@@ -0,0 +1,509 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::Broadcast;
+use crate::targets::{Inclusion, ListPage, Payload, Target};
+use anyhow::Result;
+use async_trait::async_trait;
+use recall_sdk::machine::bucket::Bucket;
+use tokio::io::AsyncWrite;
+use tracing::{debug, warn};
+
+/// Sequence rejections in a row after which the sequence is read from the chain again
+const MAX_REJECTIONS: u32 = 8;
+
+/// Writes of a target whose transactions are signed with the sequence number of the
+/// account, sent with the sequence number the caller reserved for them
+#[async_trait]
+pub trait SequencedWrites: Send + Sync {
+    /// The sequence number the chain expects next from the account
+    async fn sequence(&self) -> Result<u64>;
+
+    #[allow(clippy::too_many_arguments)]
+    async fn add_object_at(
+        &self,
+        sequence: u64,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>>;
+
+    async fn delete_object_at(&self, sequence: u64, bucket: &Bucket, key: &str) -> Result<()>;
+}
+
+/// A transaction was rejected because the chain expected another sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceError {
+    pub expected: u64,
+    pub got: u64,
+}
+
+impl std::fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected sequence {}, got {}", self.expected, self.got)
+    }
+}
+
+impl std::error::Error for SequenceError {}
+
+impl SequenceError {
+    /// Finds the sequence rejection in `error`. The SDK only passes on the `info` of the
+    /// node's check of the transaction, so this falls back to parsing it. See
+    /// [SequenceError::mentioned] for rejections it can't parse
+    pub fn find(error: &anyhow::Error) -> Option<Self> {
+        error.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<Self>()
+                .copied()
+                .or_else(|| Self::parse(&cause.to_string()))
+        })
+    }
+
+    fn parse(message: &str) -> Option<Self> {
+        let (_, rest) = message.split_once("expected sequence ")?;
+        let (expected, rest) = rest.split_once(", got ")?;
+        let got: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        Some(Self {
+            expected: expected.trim().parse().ok()?,
+            got: got.parse().ok()?,
+        })
+    }
+
+    /// Whether `error` looks like a sequence rejection in a format [SequenceError::find]
+    /// doesn't know
+    fn mentioned(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            let message = cause.to_string().to_lowercase();
+            message.contains("sequence") || message.contains("nonce")
+        })
+    }
+}
+
+/// Sequence numbers that were handed out but haven't landed
+#[derive(Default)]
+struct Nonces {
+    /// Sent and not settled yet
+    in_flight: BTreeSet<u64>,
+    /// Below `next`, but never landed. They are handed out again before `next`, so the
+    /// chain doesn't wait on them
+    gaps: BTreeSet<u64>,
+}
+
+/// Coordinates the writes of one account that keeps several transactions in flight.
+///
+/// Sequence numbers are reserved locally, so concurrent writes don't wait on each other,
+/// and each write is signed with the one it reserved. A write that fails without landing
+/// hands its sequence number back, and the next write fills that gap. When the chain
+/// rejects a write for its sequence number, the rejection tells which number it expected:
+/// either the numbers below it already landed and are skipped, or the numbers between it
+/// and the rejected one are missing and are handed out again. The rejected write itself
+/// fails like any other, since its payload was already read. Only when rejections keep
+/// coming, or one doesn't tell the expected number, is the sequence read from the chain
+/// again.
+///
+/// Targets without [SequencedWrites] are passed through as they are.
+pub struct SequencedTarget {
+    inner: Arc<dyn Target>,
+    /// The lowest sequence number that was never handed out
+    next: AtomicU64,
+    nonces: Mutex<Nonces>,
+    /// Whether `next` follows the chain, it is read again when it doesn't
+    synced: AtomicBool,
+    /// Held while reading the sequence from the chain
+    sync: tokio::sync::Mutex<()>,
+    /// Sequence rejections since the last write that landed
+    rejections: AtomicU32,
+    /// How many times the sequence was read from the chain
+    resyncs: AtomicU64,
+}
+
+impl SequencedTarget {
+    pub fn new(inner: Arc<dyn Target>) -> Self {
+        Self {
+            inner,
+            next: AtomicU64::new(0),
+            nonces: Mutex::new(Nonces::default()),
+            synced: AtomicBool::new(false),
+            sync: tokio::sync::Mutex::new(()),
+            rejections: AtomicU32::new(0),
+            resyncs: AtomicU64::new(0),
+        }
+    }
+
+    /// Wraps `target` if it gets concurrent writes, otherwise returns it as is
+    pub fn wrap(target: Arc<dyn Target>, concurrent_writes: bool) -> Arc<dyn Target> {
+        if concurrent_writes {
+            Arc::new(Self::new(target))
+        } else {
+            target
+        }
+    }
+
+    /// How many times the sequence was read from the chain, including the first time
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs.load(Ordering::SeqCst)
+    }
+
+    /// Reads the sequence from the chain, unless another write already did
+    async fn sync(&self, writes: &dyn SequencedWrites) -> Result<()> {
+        let _sync = self.sync.lock().await;
+        if self.synced.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let sequence = writes.sequence().await?;
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.gaps.clear();
+        self.next.store(sequence, Ordering::SeqCst);
+        self.rejections.store(0, Ordering::SeqCst);
+        self.resyncs.fetch_add(1, Ordering::SeqCst);
+        self.synced.store(true, Ordering::SeqCst);
+        debug!(sequence, "read sequence from the chain");
+        Ok(())
+    }
+
+    /// Reserves the sequence number of the next write, filling the lowest gap first
+    async fn reserve(&self, writes: &dyn SequencedWrites) -> Result<u64> {
+        if !self.synced.load(Ordering::SeqCst) {
+            self.sync(writes).await?;
+        }
+        let mut nonces = self.nonces.lock().unwrap();
+        let sequence = match nonces.gaps.pop_first() {
+            Some(sequence) => sequence,
+            None => self.next.fetch_add(1, Ordering::SeqCst),
+        };
+        nonces.in_flight.insert(sequence);
+        Ok(sequence)
+    }
+
+    /// Updates the reserved sequence numbers with how the write with `sequence` went
+    fn settle<T>(&self, sequence: u64, result: &Result<T>) {
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.in_flight.remove(&sequence);
+        let Err(error) = result else {
+            self.rejections.store(0, Ordering::SeqCst);
+            return;
+        };
+        let Some(rejection) = SequenceError::find(error) else {
+            // it never landed, so the chain still waits for it
+            nonces.gaps.insert(sequence);
+            if SequenceError::mentioned(error) && self.synced.swap(false, Ordering::SeqCst) {
+                warn!(error = %error, "can't tell which sequence the chain expected, reading it");
+            }
+            return;
+        };
+
+        if rejection.expected > sequence {
+            // everything below the expected sequence landed, some of it without us knowing
+            nonces.gaps.retain(|gap| *gap >= rejection.expected);
+            self.next.fetch_max(rejection.expected, Ordering::SeqCst);
+        } else {
+            // the numbers from the expected one up are missing, except those still in flight
+            let missing: Vec<u64> = (rejection.expected..=sequence)
+                .filter(|missing| !nonces.in_flight.contains(missing))
+                .collect();
+            nonces.gaps.extend(missing);
+        }
+        let rejections = self.rejections.fetch_add(1, Ordering::SeqCst) + 1;
+        debug!(
+            sequence,
+            expected = rejection.expected,
+            rejections,
+            "sequence rejected"
+        );
+        if rejections >= MAX_REJECTIONS && self.synced.swap(false, Ordering::SeqCst) {
+            warn!(
+                rejections,
+                "sequence keeps getting rejected, reading it from the chain"
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Target for SequencedTarget {
+    /// Bucket creation signs with the account's own wallet, so the sequence is read again
+    /// before the next write
+    async fn create_bucket(&self) -> Result<Bucket> {
+        let bucket = self.inner.create_bucket().await;
+        self.synced.store(false, Ordering::SeqCst);
+        bucket
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<String>, Option<Vec<u8>>)> {
+        self.inner
+            .list_objects(bucket, prefix, start_key, limit)
+            .await
+    }
+
+    async fn list_directory(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        delimiter: &str,
+        start_key: Option<Vec<u8>>,
+        limit: Option<u64>,
+    ) -> Result<ListPage> {
+        self.inner
+            .list_directory(bucket, prefix, delimiter, start_key, limit)
+            .await
+    }
+
+    async fn add_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        payload: Payload,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        broadcast_mode: Broadcast,
+    ) -> Result<Option<String>> {
+        let Some(writes) = self.inner.sequenced() else {
+            return self
+                .inner
+                .add_object(bucket, key, payload, metadata, overwrite, broadcast_mode)
+                .await;
+        };
+        let sequence = self.reserve(writes).await?;
+        let result = writes
+            .add_object_at(
+                sequence,
+                bucket,
+                key,
+                payload,
+                metadata,
+                overwrite,
+                broadcast_mode,
+            )
+            .await;
+        self.settle(sequence, &result);
+        result
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &Bucket,
+        key: &str,
+        writer: Box<dyn AsyncWrite + Unpin + Send + 'static>,
+        range: Option<String>,
+    ) -> Result<()> {
+        self.inner.get_object(bucket, key, writer, range).await
+    }
+
+    async fn delete_object(&self, bucket: &Bucket, key: &str) -> Result<()> {
+        let Some(writes) = self.inner.sequenced() else {
+            return self.inner.delete_object(bucket, key).await;
+        };
+        let sequence = self.reserve(writes).await?;
+        let result = writes.delete_object_at(sequence, bucket, key).await;
+        self.settle(sequence, &result);
+        result
+    }
+
+    /// Failed writes already repair the sequence, so there is nothing to resync
+    async fn resync(&self) -> Result<()> {
+        if self.inner.sequenced().is_none() {
+            return self.inner.resync().await;
+        }
+        Ok(())
+    }
+
+    async fn inclusion(&self, hash: &str) -> Result<Inclusion> {
+        self.inner.inclusion(hash).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MemoryConfig, MemoryOp};
+    use crate::targets::memory::InMemoryTarget;
+    use futures::future::join_all;
+
+    fn payload() -> Payload {
+        Payload::new(std::io::Cursor::new(vec![7; 64]), 64)
+    }
+
+    /// The `info` of the node's check of a transaction with a stale sequence, as the SDK
+    /// passes it on in the broadcast error
+    const CHECK_TX_INFO: &str = "expected sequence 7, got 9";
+
+    #[test]
+    fn test_find_sequence_error() {
+        let rejection = Some(SequenceError {
+            expected: 7,
+            got: 9,
+        });
+        let error = anyhow::anyhow!("broadcast failed: check tx: {CHECK_TX_INFO}");
+        assert_eq!(
+            rejection,
+            SequenceError::find(&error.context("failed to add object"))
+        );
+        // the whole response, as it shows up when it is debug formatted
+        let error = anyhow::anyhow!(
+            "check tx failed: Response {{ code: Err(2), data: b\"\", log: \"\", info: \
+             \"{CHECK_TX_INFO}\", gas_wanted: 0, gas_used: 0 }}"
+        );
+        assert_eq!(rejection, SequenceError::find(&error));
+        let error = anyhow::Error::from(SequenceError {
+            expected: 3,
+            got: 1,
+        });
+        assert_eq!(Some(3), SequenceError::find(&error).map(|e| e.expected));
+        assert_eq!(None, SequenceError::find(&anyhow::anyhow!("timed out")));
+    }
+
+    #[test]
+    fn test_reads_sequence_on_unknown_rejections() {
+        let target = SequencedTarget::new(Arc::new(InMemoryTarget::default()));
+        target.synced.store(true, Ordering::SeqCst);
+        target.settle::<()>(0, &Err(anyhow::anyhow!("timed out")));
+        assert!(target.synced.load(Ordering::SeqCst));
+
+        // the node changed how it words the rejection
+        let error = anyhow::anyhow!("invalid nonce: wanted 7 but the message has 9");
+        target.settle::<()>(1, &Err(error));
+        assert!(!target.synced.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_memory_rejects_out_of_order_sequences() {
+        let memory = InMemoryTarget::default();
+        let bucket = memory.create_bucket().await.unwrap();
+        let add = |sequence: u64| {
+            let memory = &memory;
+            let bucket = &bucket;
+            async move {
+                memory
+                    .add_object_at(
+                        sequence,
+                        bucket,
+                        &format!("foo/{sequence}"),
+                        payload(),
+                        HashMap::new(),
+                        true,
+                        Broadcast::Commit,
+                    )
+                    .await
+            }
+        };
+
+        let error = add(1).await.unwrap_err();
+        assert_eq!(
+            Some(SequenceError {
+                expected: 0,
+                got: 1
+            }),
+            SequenceError::find(&error)
+        );
+        add(0).await.unwrap();
+        add(1).await.unwrap();
+        let error = add(1).await.unwrap_err();
+        assert_eq!(Some(2), SequenceError::find(&error).map(|e| e.expected));
+        assert_eq!(2, memory.sequence().await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fills_gaps_without_resyncing() {
+        let memory = MemoryConfig {
+            put: MemoryOp {
+                latency_ms: 50,
+                failure_rate: 0.0,
+            },
+            ..Default::default()
+        };
+        let memory = Arc::new(InMemoryTarget::new(memory));
+        let target = SequencedTarget::new(memory.clone());
+        let bucket = target.create_bucket().await.unwrap();
+        let put = |key: String, overwrite: bool| {
+            let target = &target;
+            let bucket = &bucket;
+            async move {
+                target
+                    .add_object(
+                        bucket,
+                        &key,
+                        payload(),
+                        HashMap::new(),
+                        overwrite,
+                        Broadcast::Commit,
+                    )
+                    .await
+            }
+        };
+
+        // eight uploads in flight get consecutive sequence numbers
+        let results = join_all((0..8).map(|i| put(format!("foo/{i}"), true))).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(8, memory.sequence().await.unwrap());
+
+        // the first write never lands, the ones in flight after it are rejected for the gap
+        let keys = ["foo/0", "foo/8", "foo/9", "foo/10"];
+        let results = join_all(keys.map(|key| put(key.to_string(), false))).await;
+        assert!(results.iter().all(Result::is_err));
+        let rejected = results
+            .iter()
+            .filter_map(|result| SequenceError::find(result.as_ref().unwrap_err()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![8, 8, 8],
+            rejected.iter().map(|e| e.expected).collect::<Vec<_>>()
+        );
+        assert_eq!(8, memory.sequence().await.unwrap());
+
+        // the next writes fill the gap from the lowest number up
+        for i in 8..12 {
+            put(format!("foo/{i}"), true).await.unwrap();
+        }
+        assert_eq!(12, memory.sequence().await.unwrap());
+        assert_eq!(1, target.resyncs());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_skips_sequences_that_landed() {
+        let memory = Arc::new(InMemoryTarget::default());
+        let target = SequencedTarget::new(memory.clone());
+        let bucket = target.create_bucket().await.unwrap();
+        let put = |key: &'static str| {
+            target.add_object(
+                &bucket,
+                key,
+                payload(),
+                HashMap::new(),
+                true,
+                Broadcast::Commit,
+            )
+        };
+        put("foo/1").await.unwrap();
+
+        // another wallet of the account sent transactions behind the target's back
+        for sequence in 1..4 {
+            memory
+                .add_object_at(
+                    sequence,
+                    &bucket,
+                    "foo/2",
+                    payload(),
+                    HashMap::new(),
+                    true,
+                    Broadcast::Commit,
+                )
+                .await
+                .unwrap();
+        }
+        let error = put("foo/3").await.unwrap_err();
+        assert_eq!(Some(4), SequenceError::find(&error).map(|e| e.expected));
+        put("foo/3").await.unwrap();
+        assert_eq!(5, memory.sequence().await.unwrap());
+        assert_eq!(1, target.resyncs());
+    }
+}
@@ -0,0 +1,187 @@
+//! A minimal HTTP/1.1 server the S3 and HTTP target tests run their requests against
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request as the server received it
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// How long to wait before sending the status line
+    pub first_byte_delay: Duration,
+    /// How long to wait between the headers and the body
+    pub body_delay: Duration,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            first_byte_delay: Duration::ZERO,
+            body_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Serves every connection with `handler` and keeps the requests it received
+pub struct StubServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl StubServer {
+    pub async fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, handler.clone(), received.clone()));
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(stream: TcpStream, handler: Arc<Handler>, requests: Arc<Mutex<Vec<Request>>>) {
+    let mut stream = BufReader::new(stream);
+    while let Some(request) = read_request(&mut stream).await {
+        let response = handler(&request);
+        requests.lock().unwrap().push(request);
+
+        tokio::time::sleep(response.first_byte_delay).await;
+        let mut head = format!("HTTP/1.1 {} Stub\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("content-length: {}\r\n\r\n", response.body.len()));
+        let stream = stream.get_mut();
+        if stream.write_all(head.as_bytes()).await.is_err() {
+            return;
+        }
+        let _ = stream.flush().await;
+        tokio::time::sleep(response.body_delay).await;
+        if stream.write_all(&response.body).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let url = url::Url::parse(&format!("http://stub{target}")).ok()?;
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let mut body = if headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.contains("chunked"))
+    {
+        read_chunked(stream).await?
+    } else {
+        let length = headers
+            .get("content-length")
+            .map_or(Some(0), |length| length.parse().ok())?;
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.ok()?;
+        body
+    };
+    // the S3 SDK frames streaming uploads itself to append a checksum trailer
+    if headers
+        .get("content-encoding")
+        .is_some_and(|encoding| encoding.contains("aws-chunked"))
+    {
+        body = read_chunked(&mut &body[..]).await?;
+    }
+
+    Some(Request {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+        body,
+    })
+}
+
+/// Reads a chunked body and skips any trailers after the last chunk
+async fn read_chunked(stream: &mut (impl AsyncBufReadExt + Unpin)) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let size = line.trim_end().split(';').next()?;
+        let size = usize::from_str_radix(size, 16).ok()?;
+        if size == 0 {
+            break;
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..]).await.ok()?;
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+    }
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await.ok()? == 0 || line.trim_end().is_empty() {
+            return Some(body);
+        }
+    }
+}